@@ -0,0 +1,53 @@
+//! Compares a proxied call, which uses the cached symbol address, against
+//! resolving the symbol with `dlsym` on every call.
+//!
+//! Run with `cargo bench`.
+
+extern crate aya_rustc_llvm_proxy;
+extern crate llvm_sys;
+
+use std::ffi::c_uint;
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+
+const ITERATIONS: u32 = 1_000_000;
+
+type GetVersion = unsafe extern "C" fn(*mut c_uint, *mut c_uint, *mut c_uint);
+
+fn bench(name: &str, mut f: impl FnMut()) {
+    // Warm up, which also resolves the cached symbol.
+    for _ in 0..ITERATIONS / 10 {
+        f();
+    }
+    let start = Instant::now();
+    for _ in 0..ITERATIONS {
+        f();
+    }
+    let elapsed = start.elapsed();
+    let per_call = Duration::from_secs_f64(elapsed.as_secs_f64() / f64::from(ITERATIONS));
+    println!("{name:>8}: {per_call:?} per call ({elapsed:?} total)");
+}
+
+fn main() {
+    let library = aya_rustc_llvm_proxy::try_load().expect("unable to load LLVM");
+    let (mut major, mut minor, mut patch) = (0, 0, 0);
+
+    bench("cached", || unsafe {
+        llvm_sys::core::LLVMGetVersion(
+            black_box(&mut major),
+            black_box(&mut minor),
+            black_box(&mut patch),
+        );
+    });
+
+    bench("dlsym", || unsafe {
+        let entry = library
+            .get::<GetVersion>(black_box(b"LLVMGetVersion"))
+            .expect("LLVMGetVersion");
+        entry(
+            black_box(&mut major),
+            black_box(&mut minor),
+            black_box(&mut patch),
+        );
+    });
+}
@@ -2,19 +2,46 @@ extern crate cargo_metadata;
 extern crate quote;
 extern crate syn;
 
+/// Selects what proxied calls do when the loaded library lacks their symbol
+/// and no handler is installed; see `set_missing_symbol_handler`.
+const MISSING_SYMBOL_ENV_VAR: &str = "RUSTC_LLVM_PROXY_MISSING_SYMBOL";
+
 fn main() {
     let out_dir = std::env::var_os("OUT_DIR").unwrap();
+    let target = std::env::var("TARGET").unwrap();
+
+    // Used to locate `<sysroot>/lib/rustlib/<target>/lib` at runtime.
+    println!("cargo:rustc-env=RUSTC_LLVM_PROXY_TARGET={target}");
+
+    println!("cargo:rerun-if-env-changed={MISSING_SYMBOL_ENV_VAR}");
+    println!(
+        "cargo:rustc-check-cfg=cfg(rustc_llvm_proxy_missing_symbol, values(\"panic\", \"abort\", \"hook\"))"
+    );
+    let missing_symbol = std::env::var(MISSING_SYMBOL_ENV_VAR);
+    let missing_symbol = missing_symbol.as_deref().unwrap_or("panic");
+    match missing_symbol {
+        "panic" | "abort" | "hook" => {
+            println!("cargo:rustc-cfg=rustc_llvm_proxy_missing_symbol=\"{missing_symbol}\"")
+        }
+        other => panic!("{MISSING_SYMBOL_ENV_VAR} must be panic, abort or hook, not {other:?}"),
+    }
 
-    llvm::Generator::default()
+    let out_dir = std::path::PathBuf::from(out_dir);
+    let mut generator = llvm::Generator::default();
+    generator
         .parse_llvm_sys_crate()
-        .expect("Unable to parse 'llvm-sys' crate")
-        .write_declarations(&std::path::PathBuf::from(out_dir).join("llvm_gen.rs"))
+        .expect("Unable to parse 'llvm-sys' crate");
+    generator
+        .write_declarations(&out_dir.join("llvm_gen.rs"))
         .expect("Unable to write generated LLVM declarations");
+    generator
+        .write_version(&out_dir.join("version_gen.rs"))
+        .expect("Unable to write generated llvm-sys version");
 }
 
 mod llvm {
     use anyhow::{format_err, Context as _, Error};
-    use cargo_metadata::{MetadataCommand, Package, Target};
+    use cargo_metadata::{semver::Version, MetadataCommand, Package, Target};
     use quote::{format_ident, quote};
     use std::{
         collections::{
@@ -25,28 +52,136 @@ mod llvm {
         path::Path,
     };
 
-    const INIT_MACROS: &[&str] = &[
-        "LLVM_InitializeAllTargetInfos",
-        "LLVM_InitializeAllTargets",
-        "LLVM_InitializeAllTargetMCs",
-        "LLVM_InitializeAllAsmPrinters",
-        "LLVM_InitializeAllAsmParsers",
-        "LLVM_InitializeAllDisassemblers",
-        "LLVM_InitializeNativeTarget",
-        "LLVM_InitializeNativeAsmParser",
-        "LLVM_InitializeNativeAsmPrinter",
-        "LLVM_InitializeNativeDisassembler",
+    macro_rules! init_macros {
+        ($($name:ident),* $(,)?) => {
+            const INIT_MACROS: &[&str] = &[$(stringify!($name)),*];
+        };
+    }
+    include!("src/init_macros.rs");
+
+    /// Modules of llvm-sys whose functions libLLVM never exports: the LTO C API
+    /// lives in libLTO. They are still proxied, for libraries that do export
+    /// them, but reported apart by `resolve_all`.
+    const UNAVAILABLE_MODULES: &[&str] = &["lto"];
+
+    /// Functions llvm-sys declares that libLLVM does not export, with the
+    /// first LLVM major version lacking them. Proxied and reported like
+    /// `UNAVAILABLE_MODULES`.
+    const UNAVAILABLE_FUNCTIONS: &[(&str, u64)] = &[
+        // Misspelling of `LLVMDbgVariableRecordGetExpression` in llvm-sys.
+        ("LVMDbgVariableRecordGetExpression", 0),
+        // Only defined by libRemarks.
+        ("LLVMRemarkVersion", 0),
+        // The `mul` constant expression was removed.
+        ("LLVMConstMul", 21),
+        ("LLVMConstNSWMul", 21),
+        ("LLVMConstNUWMul", 21),
     ];
 
     #[derive(Default)]
     pub struct Generator {
         functions: HashMap<syn::Ident, (Vec<syn::Ident>, syn::ItemFn)>,
+        llvm_sys_version: Option<Version>,
     }
 
+    /// The per-target initializers of backends llvm-sys does not declare, and
+    /// the components each backend defines in LLVM.
+    const UNDECLARED_TARGET_INITIALIZERS: &[(&str, &[&str])] = &[
+        (
+            "ARC",
+            &[
+                "TargetInfo",
+                "Target",
+                "TargetMC",
+                "AsmPrinter",
+                "Disassembler",
+            ],
+        ),
+        (
+            "AVR",
+            &[
+                "TargetInfo",
+                "Target",
+                "TargetMC",
+                "AsmPrinter",
+                "AsmParser",
+                "Disassembler",
+            ],
+        ),
+        (
+            "CSKY",
+            &[
+                "TargetInfo",
+                "Target",
+                "TargetMC",
+                "AsmPrinter",
+                "AsmParser",
+                "Disassembler",
+            ],
+        ),
+        (
+            "DirectX",
+            &["TargetInfo", "Target", "TargetMC", "AsmPrinter"],
+        ),
+        (
+            "M68k",
+            &[
+                "TargetInfo",
+                "Target",
+                "TargetMC",
+                "AsmPrinter",
+                "AsmParser",
+                "Disassembler",
+            ],
+        ),
+        ("SPIRV", &["TargetInfo", "Target", "TargetMC", "AsmPrinter"]),
+        (
+            "VE",
+            &[
+                "TargetInfo",
+                "Target",
+                "TargetMC",
+                "AsmPrinter",
+                "AsmParser",
+                "Disassembler",
+            ],
+        ),
+        (
+            "Xtensa",
+            &[
+                "TargetInfo",
+                "Target",
+                "TargetMC",
+                "AsmPrinter",
+                "AsmParser",
+                "Disassembler",
+            ],
+        ),
+    ];
+
     fn llvm_sys() -> syn::Ident {
         format_ident!("llvm_sys")
     }
 
+    /// Returns the body of a proxy function, which calls through the cached
+    /// address of `ident`.
+    fn proxy_block(
+        ident: &syn::Ident,
+        type_bare_fn: &syn::TypeBareFn,
+        input_names: &[syn::Ident],
+    ) -> syn::Block {
+        syn::parse2(quote! {
+            {
+                let entry = symbols::#ident.resolve();
+                let entry = unsafe {
+                    std::mem::transmute::<*mut std::ffi::c_void, #type_bare_fn>(entry)
+                };
+                entry(#(#input_names),*)
+            }
+        })
+        .unwrap()
+    }
+
     impl Generator {
         pub fn parse_llvm_sys_crate(&mut self) -> Result<&mut Self, Error> {
             // See https://github.com/rust-lang/cargo/pull/12783.
@@ -61,27 +196,88 @@ mod llvm {
                 .exec()
                 .context("Unable to get crate metadata")?;
 
-            let llvm_sys_src_path = metadata
+            let (llvm_sys_version, llvm_sys_src_path) = metadata
                 .packages
                 .into_iter()
-                .find_map(|Package { name, targets, .. }| {
-                    (name.as_str() == "llvm-sys")
-                        .then(|| {
-                            targets
-                                .into_iter()
-                                .find_map(|Target { name, src_path, .. }| {
-                                    (name == LLVM_SYS_CRATE_TARGET).then_some(src_path)
-                                })
-                        })
-                        .flatten()
-                })
+                .find_map(
+                    |Package {
+                         name,
+                         version,
+                         targets,
+                         ..
+                     }| {
+                        (name.as_str() == "llvm-sys")
+                            .then(|| {
+                                targets
+                                    .into_iter()
+                                    .find_map(|Target { name, src_path, .. }| {
+                                        (name == LLVM_SYS_CRATE_TARGET).then_some(src_path)
+                                    })
+                            })
+                            .flatten()
+                            .map(|src_path| (version, src_path))
+                    },
+                )
                 .ok_or_else(|| format_err!("Unable to find 'llvm-sys' in the crate metadata"))?;
+            self.llvm_sys_version = Some(llvm_sys_version);
 
             self.generate_file(llvm_sys_src_path.as_std_path(), &[llvm_sys()])?;
+            self.generate_target_initializers();
 
             Ok(self)
         }
 
+        /// Proxies the per-target `LLVMInitialize<Backend><Component>`
+        /// functions in `UNDECLARED_TARGET_INITIALIZERS`, so that callers
+        /// declaring them by hand still link.
+        pub fn generate_target_initializers(&mut self) {
+            let Self { functions, .. } = self;
+            let mod_path = vec![llvm_sys(), format_ident!("target")];
+            for (backend, components) in UNDECLARED_TARGET_INITIALIZERS {
+                for component in *components {
+                    let ident = format_ident!("LLVMInitialize{backend}{component}");
+                    let Entry::Vacant(entry) = functions.entry(ident) else {
+                        continue;
+                    };
+                    let ident = entry.key();
+                    let type_bare_fn = syn::parse2(quote! { unsafe extern "C" fn() }).unwrap();
+                    let block = proxy_block(ident, &type_bare_fn, &[]);
+                    let item_fn = syn::parse2(quote! {
+                        #[no_mangle]
+                        pub unsafe extern "C" fn #ident() #block
+                    })
+                    .unwrap();
+                    entry.insert((mod_path.clone(), item_fn));
+                }
+            }
+        }
+
+        /// Returns true if `ident` is in `UNAVAILABLE_FUNCTIONS` for the LLVM
+        /// version llvm-sys targets.
+        fn is_unavailable(&self, mod_path: &[syn::Ident], ident: &syn::Ident) -> bool {
+            if let [root, module] = mod_path {
+                if *root == llvm_sys() && UNAVAILABLE_MODULES.iter().any(|name| module == name) {
+                    return true;
+                }
+            }
+            let major = self.llvm_major();
+            UNAVAILABLE_FUNCTIONS
+                .iter()
+                .any(|(name, removed_in)| ident == name && major >= *removed_in)
+        }
+
+        /// Returns the LLVM major version llvm-sys targets.
+        fn llvm_major(&self) -> u64 {
+            let Self {
+                llvm_sys_version, ..
+            } = self;
+            let version = llvm_sys_version
+                .as_ref()
+                .expect("llvm-sys version is not known before parsing the crate");
+            // llvm-sys encodes the LLVM version as `major * 10 + minor`.
+            version.major / 10
+        }
+
         pub fn generate_mod(
             &mut self,
             fs_path: &Path,
@@ -254,24 +450,14 @@ mod llvm {
                                         output: output.clone(),
                                     };
 
-                                    let block = quote! {
-                                        {
-                                            let entry = unsafe {
-                                                crate::proxy::SHARED_LIB.get::<#type_bare_fn>(
-                                                    stringify!(#ident).as_bytes(),
-                                                )
-                                            }.expect(stringify!(#ident));
-                                            entry(#(#input_names),*)
-                                        }
-                                    };
-                                    let block = syn::parse2(block).unwrap();
+                                    let block = proxy_block(ident, &type_bare_fn, &input_names);
 
                                     let ident = ident.clone();
                                     let item_fn = syn::ItemFn {
                                         attrs: Vec::new(),
                                         vis,
                                         sig,
-                                        block,
+                                        block: Box::new(block),
                                     };
                                     let item_fn = syn::parse2(quote! {
                                         #[no_mangle]
@@ -279,7 +465,7 @@ mod llvm {
                                     })
                                     .unwrap();
 
-                                    let Self { functions } = self;
+                                    let Self { functions, .. } = self;
                                     match functions.entry(ident) {
                                         Entry::Occupied(entry) => {
                                             if entry.key() == "LLVMAddInstructionCombiningPass" {
@@ -322,9 +508,66 @@ mod llvm {
         }
 
         pub fn write_declarations(&self, path: &Path) -> io::Result<()> {
-            let Self { functions } = self;
+            let Self { functions, .. } = self;
             let mut items = Vec::new();
             let mut paths = HashSet::new();
+            let mut idents: Vec<_> = functions.keys().collect();
+            idents.sort();
+            let unavailable = idents.iter().filter(|ident| {
+                let (mod_path, _) = &functions[**ident];
+                self.is_unavailable(mod_path, ident)
+            });
+            let modules = idents.iter().map(|ident| {
+                let (mod_path, _) = &functions[*ident];
+                mod_path
+                    .iter()
+                    .map(ToString::to_string)
+                    .collect::<Vec<_>>()
+                    .join("::")
+            });
+            // Resolved addresses are cached per symbol, so that a proxied call
+            // costs an atomic load rather than a `dlsym` lookup.
+            items.push(
+                syn::parse2(quote! {
+                    #[allow(non_upper_case_globals)]
+                    mod symbols {
+                        use crate::symbol::LazySymbol;
+
+                        #(
+                            pub(super) static #idents: LazySymbol =
+                                LazySymbol::new(stringify!(#idents));
+                        )*
+                    }
+                })
+                .unwrap(),
+            );
+            items.push(
+                syn::parse2(quote! {
+                    /// The cached addresses of [`SYMBOLS`], in the same order.
+                    static LAZY_SYMBOLS: &[&crate::symbol::LazySymbol] = &[#(&symbols::#idents),*];
+                })
+                .unwrap(),
+            );
+            items.push(
+                syn::parse2(quote! {
+                    /// Every proxied symbol, sorted by name.
+                    pub static SYMBOLS: &[ProxySymbol] = &[#(
+                        ProxySymbol {
+                            name: stringify!(#idents),
+                            module: #modules,
+                        }
+                    ),*];
+                })
+                .unwrap(),
+            );
+            items.push(
+                syn::parse2(quote! {
+                    /// The proxied symbols libLLVM does not export in the LLVM
+                    /// release llvm-sys targets, sorted by name.
+                    static UNAVAILABLE_SYMBOLS: &[&str] = &[#(stringify!(#unavailable)),*];
+                })
+                .unwrap(),
+            );
             let root = [llvm_sys()];
             let prelude = [llvm_sys(), format_ident!("prelude")];
             paths.insert(root.as_slice());
@@ -355,5 +598,22 @@ mod llvm {
             let formatted = prettyplease::unparse(&file);
             fs::write(path, formatted)
         }
+
+        pub fn write_version(&self, path: &Path) -> io::Result<()> {
+            let Self {
+                llvm_sys_version, ..
+            } = self;
+            let major = u32::try_from(self.llvm_major()).unwrap();
+            let version = llvm_sys_version.as_ref().unwrap().to_string();
+            let file = quote! {
+                /// The version of the `llvm-sys` crate this crate was built against.
+                pub const LLVM_SYS_VERSION: &str = #version;
+
+                /// The LLVM major version targeted by [`LLVM_SYS_VERSION`].
+                pub const LLVM_SYS_MAJOR: u32 = #major;
+            };
+            let formatted = prettyplease::unparse(&syn::parse2(file).unwrap());
+            fs::write(path, formatted)
+        }
     }
 }
@@ -0,0 +1,29 @@
+//! Diagnostics emitted while locating and loading the LLVM shared library.
+//!
+//! Events go to `tracing` or `log` when the corresponding feature is enabled,
+//! with `tracing` taking precedence, and are dropped otherwise. Each event is a
+//! fixed message plus named fields, which `tracing` records as structured
+//! fields and `log` appends to the message.
+
+/// Emits a diagnostic event at the given level:
+///
+/// ``` text
+/// diag!(debug, "unable to read dir", dir = path.display(), error = err);
+/// ```
+///
+/// Field values must implement `Display`.
+macro_rules! diag {
+    ($level:ident, $message:literal $(, $field:ident = $value:expr)* $(,)?) => {{
+        #[cfg(feature = "tracing")]
+        tracing::$level!($($field = %$value,)* $message);
+        #[cfg(all(feature = "log", not(feature = "tracing")))]
+        log::$level!(
+            concat!($message $(, " ", stringify!($field), "={}")*)
+            $(, $value)*
+        );
+        #[cfg(not(any(feature = "log", feature = "tracing")))]
+        {
+            $(let _ = &$value;)*
+        }
+    }};
+}
@@ -0,0 +1,577 @@
+use super::elf::Elf;
+use super::loader::{self, RejectReason, RejectedCandidate, VersionPolicy};
+use super::version::{self, Version, LLVM_SYS_MAJOR, LLVM_SYS_VERSION};
+
+use std::cmp::Reverse;
+use std::collections::hash_map::{Entry, HashMap};
+use std::fmt;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// A symbol every library providing the LLVM C API exports.
+///
+/// Candidates found by the search that do not export it are rejected.
+pub const SENTINEL_SYMBOL: &str = "LLVMContextCreate";
+
+/// Where a searched directory or candidate library came from.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum SearchSource {
+    /// The path given to [`Loader::path`](crate::Loader::path).
+    LoaderPath,
+    /// The path named by [`LIB_ENV_VAR`](crate::LIB_ENV_VAR).
+    LibEnvVar,
+    /// A directory given to [`Loader::search_dir`](crate::Loader::search_dir).
+    LoaderSearchDir,
+    /// An entry of `LD_LIBRARY_PATH`.
+    LdLibraryPath,
+    /// An entry of `DYLD_FALLBACK_LIBRARY_PATH`.
+    DyldFallbackLibraryPath,
+    /// The sysroot of the active Rust toolchain.
+    Sysroot,
+    /// The directory of the running executable, or the `lib` directory next
+    /// to it.
+    Executable,
+    /// An entry of the running executable's `DT_RUNPATH` or `DT_RPATH`.
+    Rpath,
+    /// The `lib` directory next to a `PATH` entry.
+    Path,
+}
+
+impl fmt::Display for SearchSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::LoaderPath => "Loader::path",
+            Self::LibEnvVar => super::loader::LIB_ENV_VAR,
+            Self::LoaderSearchDir => "Loader::search_dir",
+            Self::LdLibraryPath => "LD_LIBRARY_PATH",
+            Self::DyldFallbackLibraryPath => "DYLD_FALLBACK_LIBRARY_PATH",
+            Self::Sysroot => "sysroot",
+            Self::Executable => "executable",
+            Self::Rpath => "rpath",
+            Self::Path => "PATH",
+        })
+    }
+}
+
+/// A directory searched for the LLVM shared library.
+#[derive(Debug)]
+pub struct SearchedDir {
+    pub path: PathBuf,
+    pub source: SearchSource,
+    /// Set when the directory could not be read.
+    pub error: Option<io::Error>,
+}
+
+/// The kind of library providing the LLVM C API.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum Provider {
+    /// The LLVM shared library, such as `libLLVM.so.21.1`.
+    LibLlvm,
+    /// The Rust compiler's `librustc_driver-*.so`, from toolchains that link
+    /// LLVM into it statically and ship no LLVM shared library.
+    RustcDriver,
+}
+
+impl Provider {
+    /// Returns the provider a library file name denotes, if any.
+    ///
+    /// LLVM libraries must be named like the whole library, such as
+    /// `libLLVM.so.21.1` or `libLLVM-21.so`, rather than a static archive, a
+    /// component library such as `libLLVMBPFCodeGen.so` or an unrelated file.
+    pub(crate) fn from_file_name(name: &str) -> Option<Self> {
+        let is_shared = |rest: &str| rest.ends_with(".so") || rest.ends_with(".dylib");
+        if let Some(rest) = name.strip_prefix("libLLVM") {
+            let is_library = match rest.strip_prefix('-') {
+                Some(rest) => is_shared(rest),
+                None => rest == ".so" || rest.starts_with(".so.") || rest == ".dylib",
+            };
+            return is_library.then_some(Self::LibLlvm);
+        }
+        let rest = name.strip_prefix("librustc_driver-")?;
+        is_shared(rest).then_some(Self::RustcDriver)
+    }
+}
+
+impl fmt::Display for Provider {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::LibLlvm => "libLLVM",
+            Self::RustcDriver => "rustc_driver",
+        })
+    }
+}
+
+/// A file that looks like the LLVM shared library.
+///
+/// On ELF platforms candidates have been checked to be shared objects that
+/// export [`SENTINEL_SYMBOL`](crate::SENTINEL_SYMBOL).
+#[derive(Clone, Debug)]
+pub struct Candidate {
+    pub path: PathBuf,
+    pub source: SearchSource,
+    /// The version parsed from the file name, if it carries one.
+    pub version: Option<Version>,
+    pub provider: Provider,
+}
+
+impl Candidate {
+    /// Returns true if the library was built by the Rust project, either
+    /// because it was found in the sysroot or because its name carries a
+    /// Rust release, as in `libLLVM.so.21.1-rust-1.90.0-stable`.
+    pub fn is_rust_shipped(&self) -> bool {
+        self.source == SearchSource::Sysroot
+            || self
+                .path
+                .file_name()
+                .and_then(|name| name.to_str())
+                .is_some_and(|name| name.contains("-rust-"))
+    }
+
+    /// Returns the key candidates are sorted by, greatest first.
+    ///
+    /// Candidates are ranked by, in order:
+    ///
+    /// 1. the provider: `librustc_driver` is only a fallback for toolchains
+    ///    without an LLVM shared library, see [`Provider`];
+    /// 2. the LLVM version in the file name: [`LLVM_SYS_MAJOR`] first, then
+    ///    names without a version, then any other version;
+    /// 3. Rust-shipped libraries before distribution ones, see
+    ///    [`Candidate::is_rust_shipped`];
+    /// 4. the newest version.
+    ///
+    /// Candidates that rank equally keep the order they were found in, each
+    /// directory being read in file name order. Of several names for one
+    /// file, only the best-ranked is a candidate.
+    pub fn rank(&self) -> impl Ord {
+        let version_rank = match self.version {
+            Some(Version { major, .. }) if major == LLVM_SYS_MAJOR => 2,
+            None => 1,
+            Some(_) => 0,
+        };
+        (
+            self.provider == Provider::LibLlvm,
+            version_rank,
+            self.is_rust_shipped(),
+            self.version,
+        )
+    }
+}
+
+/// Why a candidate would be chosen.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum ChoiceReason {
+    /// The library was named explicitly; no search takes place.
+    Explicit,
+    /// The file name carries the LLVM major version `llvm-sys` expects.
+    MatchingVersion,
+    /// The file name carries no version; it is checked once loaded.
+    UnknownVersion,
+    /// Nothing matches, and the [`VersionPolicy`] allows a mismatched
+    /// library.
+    MismatchedVersion,
+}
+
+impl fmt::Display for ChoiceReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Explicit => write!(f, "named explicitly"),
+            Self::MatchingVersion => write!(f, "file name matches LLVM {LLVM_SYS_MAJOR}"),
+            Self::UnknownVersion => write!(f, "file name has no version"),
+            Self::MismatchedVersion => write!(f, "version policy allows a mismatch"),
+        }
+    }
+}
+
+/// The candidate that would be chosen, and why.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Choice {
+    /// Index into [`DiscoveryReport::candidates`].
+    pub index: usize,
+    pub reason: ChoiceReason,
+}
+
+/// Everything the library search looks at, without loading anything.
+///
+/// Returned by [`discover`](crate::discover) and
+/// [`Loader::discover`](crate::Loader::discover). The `Display` impl prints
+/// one line per directory and candidate, suitable for verbose version output.
+///
+/// The choice is made from file names alone. Loading may still move on to a
+/// later candidate if the chosen one fails to open or reports a different
+/// version once loaded.
+#[derive(Debug)]
+pub struct DiscoveryReport {
+    /// Every directory searched, in order. Empty when the library was named
+    /// explicitly.
+    pub searched: Vec<SearchedDir>,
+    /// Every candidate found, best first; see [`Candidate::rank`].
+    pub candidates: Vec<Candidate>,
+    /// Every file named like the LLVM shared library that failed validation.
+    pub rejected: Vec<RejectedCandidate>,
+    /// The candidate that would be tried first.
+    pub choice: Option<Choice>,
+}
+
+impl DiscoveryReport {
+    /// Returns the candidate that would be tried first.
+    pub fn chosen(&self) -> Option<&Candidate> {
+        self.choice
+            .map(|Choice { index, .. }| &self.candidates[index])
+    }
+}
+
+impl fmt::Display for DiscoveryReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "LLVM shared lib discovery (llvm-sys {LLVM_SYS_VERSION}, LLVM {LLVM_SYS_MAJOR})"
+        )?;
+        for SearchedDir {
+            path,
+            source,
+            error,
+        } in &self.searched
+        {
+            write!(f, "\n  searched {} ({source})", path.display())?;
+            if let Some(error) = error {
+                write!(f, ": {error}")?;
+            }
+        }
+        for (
+            i,
+            Candidate {
+                path,
+                source,
+                version,
+                provider,
+            },
+        ) in self.candidates.iter().enumerate()
+        {
+            write!(f, "\n  candidate {} ({source}, {provider}", path.display())?;
+            if let Some(version) = version {
+                write!(f, ", LLVM {version}")?;
+            }
+            write!(f, ")")?;
+            if let Some(Choice { index, reason }) = self.choice {
+                if index == i {
+                    write!(f, ": chosen, {reason}")?;
+                }
+            }
+        }
+        for RejectedCandidate { path, reason } in &self.rejected {
+            write!(f, "\n  rejected {}: {}", path.display(), reason)?;
+        }
+        if self.choice.is_none() {
+            write!(f, "\n  no candidate would be chosen")?;
+        }
+        Ok(())
+    }
+}
+
+/// Lists the candidates for the given configuration, best first.
+pub(crate) fn discover(
+    path: Option<(PathBuf, SearchSource)>,
+    search_dirs: Vec<PathBuf>,
+    policy: VersionPolicy,
+) -> DiscoveryReport {
+    if let Some((path, source)) = path {
+        let version = version::file_name_version(&path);
+        let provider = path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .and_then(Provider::from_file_name)
+            .unwrap_or(Provider::LibLlvm);
+        return DiscoveryReport {
+            searched: Vec::new(),
+            candidates: vec![Candidate {
+                path,
+                source,
+                version,
+                provider,
+            }],
+            rejected: Vec::new(),
+            choice: Some(Choice {
+                index: 0,
+                reason: ChoiceReason::Explicit,
+            }),
+        };
+    }
+    let mut searched = Vec::new();
+    let mut candidates: Vec<Candidate> = Vec::new();
+    let mut rejected = Vec::new();
+    // The canonical paths of the files seen so far, with the index of their
+    // candidate unless they were rejected, so that symlinks to one library
+    // yield a single candidate.
+    let mut seen: HashMap<PathBuf, Option<usize>> = HashMap::new();
+    let dirs = search_dirs
+        .into_iter()
+        .map(|dir| (dir, SearchSource::LoaderSearchDir))
+        .chain(default_search_dirs());
+    for (path, source) in dirs {
+        let files = match path.read_dir() {
+            Ok(files) => files,
+            Err(err) => {
+                diag!(
+                    debug,
+                    "unable to read dir",
+                    dir = path.display(),
+                    error = err
+                );
+                searched.push(SearchedDir {
+                    path,
+                    source,
+                    error: Some(err),
+                });
+                continue;
+            }
+        };
+        // Sorted so that the outcome does not depend on the file system's
+        // order.
+        let mut files: Vec<_> = files
+            .enumerate()
+            .filter_map(|(i, file)| match file {
+                Ok(file) => Some(file.path()),
+                Err(err) => {
+                    diag!(
+                        debug,
+                        "unable to read dir entry",
+                        dir = path.display(),
+                        index = i,
+                        error = err
+                    );
+                    None
+                }
+            })
+            .collect();
+        files.sort();
+        for path in files {
+            let Some(name) = path.file_name().and_then(|name| name.to_str()) else {
+                continue;
+            };
+            let provider = match Provider::from_file_name(name) {
+                Some(Provider::LibLlvm) => Provider::LibLlvm,
+                // Only the Rust toolchain's own driver is worth considering.
+                Some(Provider::RustcDriver) if source == SearchSource::Sysroot => {
+                    Provider::RustcDriver
+                }
+                Some(Provider::RustcDriver) | None => continue,
+            };
+            // Mach-O libraries are not inspected.
+            let inspect = !name.ends_with(".dylib");
+            let canonical = match path.canonicalize() {
+                Ok(canonical) => canonical,
+                Err(err) => {
+                    loader::reject(&mut rejected, path, RejectReason::Read(err));
+                    continue;
+                }
+            };
+            let version = version::file_name_version(&path);
+            let candidate = Candidate {
+                path,
+                source,
+                version,
+                provider,
+            };
+            match seen.entry(canonical) {
+                // Another name for a library that was already found: keep
+                // whichever name ranks better.
+                Entry::Occupied(entry) => {
+                    if let Some(index) = *entry.get() {
+                        let existing = &mut candidates[index];
+                        if candidate.rank() > existing.rank() {
+                            *existing = candidate;
+                        }
+                    }
+                    continue;
+                }
+                Entry::Vacant(entry) => {
+                    if inspect {
+                        if let Err(reason) = validate(&candidate.path) {
+                            entry.insert(None);
+                            loader::reject(&mut rejected, candidate.path, reason);
+                            continue;
+                        }
+                    }
+                    entry.insert(Some(candidates.len()));
+                    candidates.push(candidate);
+                }
+            }
+        }
+        searched.push(SearchedDir {
+            path,
+            source,
+            error: None,
+        });
+    }
+    candidates.sort_by_key(|candidate| Reverse(candidate.rank()));
+    let choice = choose(&candidates, policy);
+    DiscoveryReport {
+        searched,
+        candidates,
+        rejected,
+        choice,
+    }
+}
+
+/// Checks that `path` is an ELF shared object exporting [`SENTINEL_SYMBOL`].
+fn validate(path: &Path) -> Result<(), RejectReason> {
+    let mut elf = match Elf::open(path) {
+        Ok(Some(elf)) => elf,
+        Ok(None) => return Err(RejectReason::NotSharedObject),
+        Err(err) => return Err(RejectReason::Read(err)),
+    };
+    if !elf.is_shared_object() {
+        return Err(RejectReason::NotSharedObject);
+    }
+    match elf.exports(SENTINEL_SYMBOL) {
+        Ok(true) => Ok(()),
+        Ok(false) => Err(RejectReason::MissingSymbol(SENTINEL_SYMBOL)),
+        Err(err) => Err(RejectReason::Read(err)),
+    }
+}
+
+/// Picks the best candidate not known to mismatch, falling back to the best
+/// candidate when the policy allows a mismatch.
+fn choose(candidates: &[Candidate], policy: VersionPolicy) -> Option<Choice> {
+    let index = match policy {
+        VersionPolicy::Ignore => (!candidates.is_empty()).then_some(0),
+        VersionPolicy::Strict | VersionPolicy::Warn => candidates
+            .iter()
+            .position(|Candidate { version, .. }| !name_mismatches(version))
+            .or_else(|| (policy == VersionPolicy::Warn && !candidates.is_empty()).then_some(0)),
+    }?;
+    let reason = match candidates[index].version {
+        None => ChoiceReason::UnknownVersion,
+        Some(Version { major, .. }) if major == LLVM_SYS_MAJOR => ChoiceReason::MatchingVersion,
+        Some(_) => ChoiceReason::MismatchedVersion,
+    };
+    Some(Choice { index, reason })
+}
+
+/// Returns true if a version parsed from a file name is known to mismatch.
+pub(crate) fn name_mismatches(version: &Option<Version>) -> bool {
+    version.is_some_and(|Version { major, .. }| major != LLVM_SYS_MAJOR)
+}
+
+/// Returns the directories to scan for the LLVM shared library, in order.
+///
+/// The library search path variables come first, then the sysroot of the
+/// active Rust toolchain, then the directories the running executable would
+/// find bundled libraries in, and finally the `lib` directories next to
+/// `PATH` entries.
+fn default_search_dirs() -> impl Iterator<Item = (PathBuf, SearchSource)> {
+    let env_dirs = |var, source| {
+        std::env::var_os(var)
+            .map(|unparsed| std::env::split_paths(&unparsed).collect::<Vec<_>>())
+            .unwrap_or_default()
+            .into_iter()
+            .map(move |path| (path, source))
+    };
+    let bin_dirs = env_dirs("PATH", SearchSource::Path).map(|(mut path, source)| {
+        path.pop();
+        path.push("lib");
+        (path, source)
+    });
+    env_dirs("LD_LIBRARY_PATH", SearchSource::LdLibraryPath)
+        .chain(env_dirs(
+            "DYLD_FALLBACK_LIBRARY_PATH",
+            SearchSource::DyldFallbackLibraryPath,
+        ))
+        .chain(
+            sysroot_dirs()
+                .into_iter()
+                .map(|path| (path, SearchSource::Sysroot)),
+        )
+        .chain(executable_dirs())
+        .chain(bin_dirs)
+}
+
+/// Returns the directory of the running executable, the `lib` directory next
+/// to it, and the entries of its `DT_RUNPATH` or `DT_RPATH` with `$ORIGIN`
+/// expanded, for deployments that ship the LLVM library alongside the binary.
+fn executable_dirs() -> Vec<(PathBuf, SearchSource)> {
+    let exe = match std::env::current_exe() {
+        Ok(exe) => exe,
+        Err(err) => {
+            diag!(debug, "unable to locate the executable", error = err);
+            return Vec::new();
+        }
+    };
+    let Some(origin) = exe.parent() else {
+        return Vec::new();
+    };
+    let mut dirs = vec![
+        (origin.to_owned(), SearchSource::Executable),
+        (origin.with_file_name("lib"), SearchSource::Executable),
+    ];
+    let runpath = Elf::open(&exe).and_then(|elf| match elf {
+        Some(mut elf) => elf.runpath(),
+        None => Ok(Vec::new()),
+    });
+    let runpath = match runpath {
+        Ok(runpath) => runpath,
+        Err(err) => {
+            diag!(
+                debug,
+                "unable to read the executable's runpath",
+                exe = exe.display(),
+                error = err
+            );
+            return dirs;
+        }
+    };
+    let origin = origin.to_string_lossy();
+    for entry in runpath {
+        let entry = entry
+            .replace("${ORIGIN}", &origin)
+            .replace("$ORIGIN", &origin);
+        // Other dynamic string tokens, such as `$LIB`, are platform specific.
+        if entry.contains('$') {
+            diag!(debug, "skipping runpath entry", entry = entry);
+            continue;
+        }
+        dirs.push((entry.into(), SearchSource::Rpath));
+    }
+    dirs
+}
+
+/// Returns the library directories of the sysroot reported by
+/// `rustc --print sysroot`, honouring the `RUSTC` environment variable.
+fn sysroot_dirs() -> Vec<PathBuf> {
+    let rustc = std::env::var_os("RUSTC").unwrap_or_else(|| "rustc".into());
+    let output = match Command::new(&rustc).args(["--print", "sysroot"]).output() {
+        Ok(output) => output,
+        Err(err) => {
+            diag!(
+                debug,
+                "unable to run rustc --print sysroot",
+                rustc = Path::new(&rustc).display(),
+                error = err
+            );
+            return Vec::new();
+        }
+    };
+    if !output.status.success() {
+        diag!(
+            debug,
+            "rustc --print sysroot failed",
+            rustc = Path::new(&rustc).display(),
+            stderr = String::from_utf8_lossy(&output.stderr).trim()
+        );
+        return Vec::new();
+    }
+    let sysroot = String::from_utf8_lossy(&output.stdout);
+    let sysroot = Path::new(sysroot.trim());
+    vec![
+        sysroot.join("lib"),
+        sysroot
+            .join("lib")
+            .join("rustlib")
+            .join(env!("RUSTC_LLVM_PROXY_TARGET"))
+            .join("lib"),
+    ]
+}
@@ -0,0 +1,259 @@
+//! Just enough of an ELF reader to tell shared objects apart, list the symbols
+//! they export and read their library search path.
+//!
+//! Only the headers and the sections that are needed are read, since the LLVM
+//! shared library is far too large to read whole.
+
+use std::fs::File;
+use std::io::{self, Read, Seek, SeekFrom};
+use std::path::Path;
+
+const ELF_MAGIC: &[u8; 4] = b"\x7fELF";
+const ELFCLASS64: u8 = 2;
+const ELFDATA2LSB: u8 = 1;
+const ET_DYN: u16 = 3;
+const SHT_DYNAMIC: u32 = 6;
+const SHT_DYNSYM: u32 = 11;
+const DT_NULL: u64 = 0;
+const DT_RPATH: u64 = 15;
+const DT_RUNPATH: u64 = 29;
+const SHN_UNDEF: u16 = 0;
+
+/// An ELF file, with its header parsed.
+pub(crate) struct Elf {
+    file: File,
+    is_64: bool,
+    is_le: bool,
+    kind: u16,
+    shoff: u64,
+    shentsize: u16,
+    shnum: u16,
+}
+
+/// The parts of a section header this module uses.
+struct Section {
+    kind: u32,
+    offset: u64,
+    size: u64,
+    link: u32,
+    entsize: u64,
+}
+
+impl Elf {
+    /// Opens `path`, returning `Ok(None)` if it is not an ELF file.
+    pub(crate) fn open(path: &Path) -> io::Result<Option<Self>> {
+        let mut file = File::open(path)?;
+        let mut ident = [0; 16];
+        match file.read_exact(&mut ident) {
+            Ok(()) => {}
+            Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+            Err(err) => return Err(err),
+        }
+        if &ident[..4] != ELF_MAGIC {
+            return Ok(None);
+        }
+        let is_64 = ident[4] == ELFCLASS64;
+        let is_le = ident[5] == ELFDATA2LSB;
+        let mut elf = Self {
+            file,
+            is_64,
+            is_le,
+            kind: 0,
+            shoff: 0,
+            shentsize: 0,
+            shnum: 0,
+        };
+        let header = elf.read_at(0, if is_64 { 64 } else { 52 })?;
+        let (shoff, rest) = if is_64 {
+            (elf.u64(&header[40..]), &header[58..])
+        } else {
+            (elf.u32(&header[32..]).into(), &header[46..])
+        };
+        elf.kind = elf.u16(&header[16..]);
+        elf.shoff = shoff;
+        elf.shentsize = elf.u16(rest);
+        elf.shnum = elf.u16(&rest[2..]);
+        Ok(Some(elf))
+    }
+
+    /// Returns true if this is a shared object rather than an executable or
+    /// a relocatable object.
+    pub(crate) fn is_shared_object(&self) -> bool {
+        self.kind == ET_DYN
+    }
+
+    /// Returns true if `name` is defined in `.dynsym`.
+    pub(crate) fn exports(&mut self, name: &str) -> io::Result<bool> {
+        let mut found = false;
+        self.visit_dynamic_symbols(|symbol| found |= symbol == name.as_bytes())?;
+        Ok(found)
+    }
+
+    /// Returns the entries of `DT_RUNPATH`, or of `DT_RPATH` when there is no
+    /// `DT_RUNPATH`, as the dynamic linker does. Tokens such as `$ORIGIN` are
+    /// left for the caller to expand.
+    pub(crate) fn runpath(&mut self) -> io::Result<Vec<String>> {
+        let sections = self.sections()?;
+        let Some(dynamic) = sections.iter().find(|section| section.kind == SHT_DYNAMIC) else {
+            return Ok(Vec::new());
+        };
+        let Some(strtab) = sections.get(dynamic.link as usize) else {
+            return Err(invalid_data(
+                "dynamic section links to a missing string table",
+            ));
+        };
+        let strtab = self.read_at(strtab.offset, strtab.size)?;
+        let entries = self.read_at(dynamic.offset, dynamic.size)?;
+        let (mut rpath, mut runpath) = (None, None);
+        for entry in entries.chunks_exact(if self.is_64 { 16 } else { 8 }) {
+            let (tag, value) = if self.is_64 {
+                (self.u64(entry), self.u64(&entry[8..]))
+            } else {
+                (self.u32(entry).into(), self.u32(&entry[4..]).into())
+            };
+            match tag {
+                DT_NULL => break,
+                DT_RPATH => rpath = Some(value),
+                DT_RUNPATH => runpath = Some(value),
+                _ => {}
+            }
+        }
+        let Some(offset) = runpath.or(rpath) else {
+            return Ok(Vec::new());
+        };
+        let Some(value) = usize::try_from(offset)
+            .ok()
+            .and_then(|offset| strtab.get(offset..))
+        else {
+            return Err(invalid_data("runpath is outside the string table"));
+        };
+        let end = value.iter().position(|&b| b == 0).unwrap_or(value.len());
+        Ok(String::from_utf8_lossy(&value[..end])
+            .split(':')
+            .filter(|entry| !entry.is_empty())
+            .map(ToOwned::to_owned)
+            .collect())
+    }
+
+    /// Calls `visit` with the name of every symbol defined in `.dynsym`.
+    pub(crate) fn visit_dynamic_symbols(&mut self, mut visit: impl FnMut(&[u8])) -> io::Result<()> {
+        let sections = self.sections()?;
+        let Some(dynsym) = sections.iter().find(|section| section.kind == SHT_DYNSYM) else {
+            return Ok(());
+        };
+        let Some(strtab) = sections.get(dynsym.link as usize) else {
+            return Err(invalid_data("dynsym links to a missing string table"));
+        };
+        let strtab = self.read_at(strtab.offset, strtab.size)?;
+        let symtab = self.read_at(dynsym.offset, dynsym.size)?;
+        let min_entsize = if self.is_64 { 24 } else { 16 };
+        let entsize = match dynsym.entsize {
+            0 => min_entsize as usize,
+            entsize if entsize < min_entsize => {
+                return Err(invalid_data("dynsym entries are too small"))
+            }
+            entsize => entsize as usize,
+        };
+        for symbol in symtab.chunks_exact(entsize) {
+            let name = self.u32(symbol) as usize;
+            let shndx = if self.is_64 {
+                self.u16(&symbol[6..])
+            } else {
+                self.u16(&symbol[14..])
+            };
+            if shndx == SHN_UNDEF {
+                continue;
+            }
+            let Some(name) = strtab.get(name..) else {
+                continue;
+            };
+            let end = name.iter().position(|&b| b == 0).unwrap_or(name.len());
+            visit(&name[..end]);
+        }
+        Ok(())
+    }
+
+    fn sections(&mut self) -> io::Result<Vec<Section>> {
+        let Self {
+            shoff,
+            shentsize,
+            shnum,
+            ..
+        } = *self;
+        let min_shentsize = if self.is_64 { 64 } else { 40 };
+        if shentsize < min_shentsize {
+            return Ok(Vec::new());
+        }
+        let headers = self.read_at(shoff, u64::from(shentsize) * u64::from(shnum))?;
+        let sections = headers
+            .chunks_exact(shentsize.into())
+            .map(|header| {
+                if self.is_64 {
+                    Section {
+                        kind: self.u32(&header[4..]),
+                        offset: self.u64(&header[24..]),
+                        size: self.u64(&header[32..]),
+                        link: self.u32(&header[40..]),
+                        entsize: self.u64(&header[56..]),
+                    }
+                } else {
+                    Section {
+                        kind: self.u32(&header[4..]),
+                        offset: self.u32(&header[16..]).into(),
+                        size: self.u32(&header[20..]).into(),
+                        link: self.u32(&header[24..]),
+                        entsize: self.u32(&header[36..]).into(),
+                    }
+                }
+            })
+            .collect();
+        Ok(sections)
+    }
+
+    /// Reads `len` bytes at `offset`, checking first that they are within the
+    /// file so that a corrupt header cannot trigger a huge allocation.
+    fn read_at(&mut self, offset: u64, len: u64) -> io::Result<Vec<u8>> {
+        let end = offset
+            .checked_add(len)
+            .ok_or_else(|| invalid_data("section out of bounds"))?;
+        if end > self.file.metadata()?.len() {
+            return Err(invalid_data("section out of bounds"));
+        }
+        let len = usize::try_from(len).map_err(|_| invalid_data("section too large"))?;
+        let mut buf = vec![0; len];
+        self.file.seek(SeekFrom::Start(offset))?;
+        self.file.read_exact(&mut buf)?;
+        Ok(buf)
+    }
+
+    fn u16(&self, bytes: &[u8]) -> u16 {
+        let bytes = bytes[..2].try_into().unwrap();
+        if self.is_le {
+            u16::from_le_bytes(bytes)
+        } else {
+            u16::from_be_bytes(bytes)
+        }
+    }
+
+    fn u32(&self, bytes: &[u8]) -> u32 {
+        let bytes = bytes[..4].try_into().unwrap();
+        if self.is_le {
+            u32::from_le_bytes(bytes)
+        } else {
+            u32::from_be_bytes(bytes)
+        }
+    }
+
+    fn u64(&self, bytes: &[u8]) -> u64 {
+        let bytes = bytes[..8].try_into().unwrap();
+        if self.is_le {
+            u64::from_le_bytes(bytes)
+        } else {
+            u64::from_be_bytes(bytes)
+        }
+    }
+}
+
+fn invalid_data(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
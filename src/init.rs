@@ -1,99 +1,614 @@
-use super::SHARED_LIB;
+use super::elf::Elf;
+use super::{loader, try_load, LoadError};
+use libloading::Library;
 use llvm_sys::prelude::LLVMBool;
 
-use std::io::{BufRead, BufReader, Result};
-use std::process::Command;
-
-const POSSIBLE_BACKENDS: &[&str] = &[
-    "AArch64", "AMDGPU", "ARM", "BPF", "Hexagon", "Lanai", "Mips", "MSP430", "NVPTX", "PowerPC",
-    "Sparc", "SystemZ", "X86", "XCore",
-];
-
-fn get_native_arch() -> Result<String> {
-    let output = Command::new("rustc").args(["--print", "cfg"]).output()?;
-    let buf = BufReader::new(output.stdout.as_slice());
-    for line in buf.lines() {
-        let line = line?;
-        if !line.starts_with("target_arch") {
-            continue;
+use std::collections::BTreeSet;
+use std::ffi::{CStr, CString};
+use std::fmt;
+use std::io;
+use std::ops::BitOr;
+use std::path::Path;
+use std::ptr;
+use std::sync::{Mutex, MutexGuard, OnceLock, PoisonError};
+
+/// An LLVM backend.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[non_exhaustive]
+pub enum Backend {
+    AArch64,
+    AMDGPU,
+    ARC,
+    ARM,
+    AVR,
+    BPF,
+    CSKY,
+    DirectX,
+    Hexagon,
+    Lanai,
+    LoongArch,
+    M68k,
+    Mips,
+    MSP430,
+    NVPTX,
+    PowerPC,
+    RISCV,
+    Sparc,
+    SPIRV,
+    SystemZ,
+    VE,
+    WebAssembly,
+    X86,
+    XCore,
+    Xtensa,
+}
+
+impl Backend {
+    /// Every backend LLVM can be built with.
+    pub const ALL: &'static [Self] = &[
+        Self::AArch64,
+        Self::AMDGPU,
+        Self::ARC,
+        Self::ARM,
+        Self::AVR,
+        Self::BPF,
+        Self::CSKY,
+        Self::DirectX,
+        Self::Hexagon,
+        Self::Lanai,
+        Self::LoongArch,
+        Self::M68k,
+        Self::Mips,
+        Self::MSP430,
+        Self::NVPTX,
+        Self::PowerPC,
+        Self::RISCV,
+        Self::Sparc,
+        Self::SPIRV,
+        Self::SystemZ,
+        Self::VE,
+        Self::WebAssembly,
+        Self::X86,
+        Self::XCore,
+        Self::Xtensa,
+    ];
+
+    /// Returns the name LLVM uses in the backend's entry points, e.g. `BPF` in
+    /// `LLVMInitializeBPFTarget`.
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::AArch64 => "AArch64",
+            Self::AMDGPU => "AMDGPU",
+            Self::ARC => "ARC",
+            Self::ARM => "ARM",
+            Self::AVR => "AVR",
+            Self::BPF => "BPF",
+            Self::CSKY => "CSKY",
+            Self::DirectX => "DirectX",
+            Self::Hexagon => "Hexagon",
+            Self::Lanai => "Lanai",
+            Self::LoongArch => "LoongArch",
+            Self::M68k => "M68k",
+            Self::Mips => "Mips",
+            Self::MSP430 => "MSP430",
+            Self::NVPTX => "NVPTX",
+            Self::PowerPC => "PowerPC",
+            Self::RISCV => "RISCV",
+            Self::Sparc => "Sparc",
+            Self::SPIRV => "SPIRV",
+            Self::SystemZ => "SystemZ",
+            Self::VE => "VE",
+            Self::WebAssembly => "WebAssembly",
+            Self::X86 => "X86",
+            Self::XCore => "XCore",
+            Self::Xtensa => "Xtensa",
         }
-        // line should be like: target_arch="x86_64"
-        return Ok(line.split('"').nth(1).unwrap().into());
     }
-    unreachable!("`rustc --print cfg` result is wrong");
+
+    /// Looks up a backend by its LLVM name, ignoring case.
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL
+            .iter()
+            .copied()
+            .find(|backend| backend.name().eq_ignore_ascii_case(name))
+    }
 }
 
-fn arch2backend(arch: &str) -> String {
-    match arch {
-        "aarch64" => "AArch64".into(),
-        "arm" => "ARM".into(),
-        "mips" | "mips64" => "Mips".into(),
-        "powerpc" | "powerpc64" => "PowerPC".into(),
-        "sparc" | "sparc64" => "Sparc".into(),
-        "x86" | "x86_64" => "X86".into(),
-        _ => panic!("Unknown backend: {arch}"),
+impl fmt::Display for Backend {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
     }
 }
 
-fn get_native_backend() -> String {
-    let arch = get_native_arch().expect("Fail to get native arch");
-    arch2backend(&arch)
+/// A part of a backend that is initialized separately.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Component {
+    TargetInfo,
+    Target,
+    TargetMC,
+    AsmPrinter,
+    AsmParser,
+    Disassembler,
 }
 
-unsafe fn init_all(postfix: &str) {
-    for backend in POSSIBLE_BACKENDS {
-        let name = format!("LLVMInitialize{backend}{postfix}");
-        if let Ok(entrypoint) = SHARED_LIB.get::<unsafe extern "C" fn()>(name.as_bytes()) {
-            entrypoint();
+impl Component {
+    /// Every component, in the order they must be initialized.
+    pub const ALL: &'static [Self] = &[
+        Self::TargetInfo,
+        Self::Target,
+        Self::TargetMC,
+        Self::AsmPrinter,
+        Self::AsmParser,
+        Self::Disassembler,
+    ];
+
+    /// Returns the suffix of the component's entry points, e.g. `AsmPrinter`
+    /// in `LLVMInitializeBPFAsmPrinter`.
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::TargetInfo => "TargetInfo",
+            Self::Target => "Target",
+            Self::TargetMC => "TargetMC",
+            Self::AsmPrinter => "AsmPrinter",
+            Self::AsmParser => "AsmParser",
+            Self::Disassembler => "Disassembler",
         }
     }
+
+    fn bit(self) -> u8 {
+        1 << self as u8
+    }
+}
+
+/// A set of [`Component`]s, usually built with `|`:
+///
+/// ``` no_run
+/// use aya_rustc_llvm_proxy::init::{initialize, Backend, Component};
+///
+/// initialize(
+///     Backend::BPF,
+///     Component::TargetInfo | Component::Target | Component::TargetMC,
+/// )
+/// .unwrap();
+/// ```
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub struct Components(u8);
+
+impl Components {
+    /// Every component.
+    pub fn all() -> Self {
+        Component::ALL
+            .iter()
+            .copied()
+            .map(Self::from)
+            .fold(Self::default(), BitOr::bitor)
+    }
+
+    pub fn contains(self, component: Component) -> bool {
+        self.0 & component.bit() != 0
+    }
+
+    /// Iterates over the components in the order they must be initialized.
+    pub fn iter(self) -> impl Iterator<Item = Component> {
+        Component::ALL
+            .iter()
+            .copied()
+            .filter(move |component| self.contains(*component))
+    }
+}
+
+impl From<Component> for Components {
+    fn from(component: Component) -> Self {
+        Self(component.bit())
+    }
+}
+
+impl BitOr for Components {
+    type Output = Self;
+
+    fn bitor(self, rhs: Self) -> Self {
+        Self(self.0 | rhs.0)
+    }
+}
+
+impl BitOr<Component> for Components {
+    type Output = Self;
+
+    fn bitor(self, rhs: Component) -> Self {
+        self | Self::from(rhs)
+    }
+}
+
+impl BitOr for Component {
+    type Output = Components;
+
+    fn bitor(self, rhs: Self) -> Components {
+        Components::from(self) | rhs
+    }
+}
+
+/// Errors returned by the initialization API.
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum InitError {
+    /// No LLVM backend is known for the architecture.
+    UnknownArch(String),
+    /// The loaded library does not export the named entry point, e.g.
+    /// `LLVMInitializeNVPTXAsmParser`.
+    MissingEntryPoint(String),
+    /// The LLVM shared library could not be loaded.
+    Load(LoadError),
+}
+
+impl fmt::Display for InitError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::UnknownArch(arch) => write!(f, "no LLVM backend is known for {arch}"),
+            Self::MissingEntryPoint(name) => {
+                write!(f, "LLVM shared lib does not export {name}")
+            }
+            Self::Load(err) => err.fmt(f),
+        }
+    }
+}
+
+impl std::error::Error for InitError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Load(err) => Some(err),
+            Self::UnknownArch(_) | Self::MissingEntryPoint(_) => None,
+        }
+    }
+}
+
+/// Initializes the given components of a single backend, in dependency order.
+///
+/// Unlike the `LLVM_InitializeAll*` functions, this only touches `backend`,
+/// and fails on the first entry point the loaded library does not export.
+/// Components that are already initialized are skipped, so this is cheap to
+/// call repeatedly and from several threads.
+pub fn initialize(backend: Backend, components: impl Into<Components>) -> Result<(), InitError> {
+    for component in components.into().iter() {
+        init_entry_point(backend.name(), component)?;
+    }
+    Ok(())
+}
+
+/// Initializes the given components of the backend for `triple`, returning
+/// that backend.
+///
+/// The backend is looked up in the loaded LLVM's target registry, which
+/// requires initializing the `TargetInfo` of every enabled backend. If the
+/// registry does not know the triple, the architecture is mapped using a
+/// built-in table.
+pub fn initialize_for_triple(
+    triple: &str,
+    components: impl Into<Components>,
+) -> Result<Backend, InitError> {
+    let backend = match registry_backend(triple)? {
+        Some(backend) => backend,
+        None => arch2backend(triple)?,
+    };
+    initialize(backend, components)?;
+    Ok(backend)
+}
+
+/// Looks up the backend for `triple` in the loaded LLVM's target registry.
+fn registry_backend(triple: &str) -> Result<Option<Backend>, InitError> {
+    for backend in enabled_backends().map_err(InitError::Load)? {
+        match init_entry_point(backend, Component::TargetInfo) {
+            Ok(()) | Err(InitError::MissingEntryPoint(_)) => {}
+            Err(err) => return Err(err),
+        }
+    }
+    let Ok(triple) = CString::new(triple) else {
+        return Ok(None);
+    };
+    let mut target = ptr::null_mut();
+    let mut error = ptr::null_mut();
+    unsafe {
+        if crate::proxy::LLVMGetTargetFromTriple(triple.as_ptr(), &mut target, &mut error) != 0 {
+            if !error.is_null() {
+                crate::proxy::LLVMDisposeMessage(error);
+            }
+            return Ok(None);
+        }
+        let name = CStr::from_ptr(crate::proxy::LLVMGetTargetName(target));
+        Ok(name.to_str().ok().and_then(|name| arch2backend(name).ok()))
+    }
+}
+
+/// Returns true if `component` of `backend` has been initialized through
+/// [`initialize`], [`initialize_for_triple`] or the `LLVM_Initialize*`
+/// exports.
+///
+/// Calls to the proxied per-target initializers, such as
+/// `llvm_sys::target::LLVMInitializeBPFTarget`, are not recorded.
+pub fn is_initialized(backend: Backend, component: Component) -> bool {
+    let name = entry_point_name(backend.name(), component);
+    initialized().contains(&name)
+}
+
+/// The entry points that have already run.
+///
+/// The lock is held while an entry point runs, so repeated or concurrent
+/// initialization runs each entry point exactly once; LLVM's target registry
+/// is not safe to populate from several threads at once.
+fn initialized() -> MutexGuard<'static, BTreeSet<String>> {
+    static INITIALIZED: Mutex<BTreeSet<String>> = Mutex::new(BTreeSet::new());
+    INITIALIZED.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+fn entry_point_name(backend: &str, component: Component) -> String {
+    format!("LLVMInitialize{backend}{}", component.name())
+}
+
+fn init_entry_point(backend: &str, component: Component) -> Result<(), InitError> {
+    let name = entry_point_name(backend, component);
+    let mut initialized = initialized();
+    if initialized.contains(&name) {
+        return Ok(());
+    }
+    let library = try_load().map_err(InitError::Load)?;
+    let entrypoint = unsafe { library.get::<unsafe extern "C" fn()>(name.as_bytes()) }
+        .map_err(|_| InitError::MissingEntryPoint(name.clone()))?;
+    unsafe { entrypoint() };
+    initialized.insert(name);
+    Ok(())
+}
+
+/// Returns the backends the loaded LLVM library was built with, sorted by
+/// name.
+///
+/// These are found from the `LLVMInitialize<Backend>TargetInfo` symbols the
+/// library exports. Loads the library if needed, and returns the error if it
+/// cannot be loaded.
+///
+/// The backends are returned by name rather than as [`Backend`]s because a
+/// newer LLVM may ship backends this crate has no variant for; those are
+/// still listed, and still initialized by `LLVM_InitializeAll*`. Use
+/// [`Backend::from_name`] to map a name to a known backend.
+pub fn available_backends() -> Result<&'static [String], LoadError> {
+    static BACKENDS: OnceLock<Vec<String>> = OnceLock::new();
+    let library = loader::default_library()?;
+    let backends = BACKENDS.get_or_init(|| match exported_backends(&library.path) {
+        Ok(Some(backends)) if !backends.is_empty() => backends,
+        Ok(_) => probe_backends(&library.library),
+        Err(err) => {
+            diag!(warn, "unable to read LLVM shared lib symbols", error = err);
+            probe_backends(&library.library)
+        }
+    });
+    Ok(backends)
+}
+
+/// Returns the available backends the `LLVM_InitializeAll*` functions
+/// initialize, honouring [`Loader::backends`](crate::Loader::backends) and
+/// [`BACKENDS_ENV_VAR`](crate::BACKENDS_ENV_VAR).
+fn enabled_backends() -> Result<impl Iterator<Item = &'static str>, LoadError> {
+    let enabled = loader::default_library()?.backends.as_deref();
+    Ok(available_backends()?
+        .iter()
+        .map(String::as_str)
+        .filter(move |backend| {
+            enabled.is_none_or(|enabled| enabled.iter().any(|enabled| enabled.name() == *backend))
+        }))
+}
+
+/// Lists the backends in the dynamic symbol table, or `None` if the library
+/// is not an ELF file.
+fn exported_backends(path: &Path) -> io::Result<Option<Vec<String>>> {
+    let Some(mut elf) = Elf::open(path)? else {
+        return Ok(None);
+    };
+    let mut backends = Vec::new();
+    elf.visit_dynamic_symbols(|symbol| {
+        let backend = symbol
+            .strip_prefix(b"LLVMInitialize")
+            .and_then(|symbol| symbol.strip_suffix(b"TargetInfo"))
+            .and_then(|backend| std::str::from_utf8(backend).ok());
+        if let Some(backend) = backend {
+            backends.push(backend.to_owned());
+        }
+    })?;
+    backends.sort();
+    backends.dedup();
+    Ok(Some(backends))
+}
+
+fn probe_backends(library: &Library) -> Vec<String> {
+    let mut backends: Vec<_> = Backend::ALL
+        .iter()
+        .map(|backend| backend.name())
+        .filter(|backend| {
+            let name = format!("LLVMInitialize{backend}TargetInfo");
+            unsafe { library.get::<unsafe extern "C" fn()>(name.as_bytes()) }.is_ok()
+        })
+        .map(ToOwned::to_owned)
+        .collect();
+    backends.sort();
+    backends
+}
+
+/// Returns the architecture this crate was compiled for, which is the
+/// architecture it runs on.
+fn get_native_arch() -> &'static str {
+    std::env::consts::ARCH
+}
+
+/// Returns the architecture of the loaded LLVM's default target triple.
+fn get_default_triple_arch() -> Option<String> {
+    unsafe {
+        let triple = crate::proxy::LLVMGetDefaultTargetTriple();
+        if triple.is_null() {
+            return None;
+        }
+        let arch = CStr::from_ptr(triple)
+            .to_str()
+            .ok()
+            .and_then(|triple| triple.split('-').next())
+            .map(ToOwned::to_owned);
+        crate::proxy::LLVMDisposeMessage(triple);
+        arch
+    }
+}
+
+/// Maps an architecture to the LLVM backend implementing it.
+///
+/// Accepts rustc architectures (`riscv64`), LLVM target names (`x86-64`) and
+/// full triples (`armv7-unknown-linux-gnueabihf`), in which case only the
+/// architecture component is considered.
+fn arch2backend(arch: &str) -> Result<Backend, InitError> {
+    let arch = arch.split('-').next().unwrap_or(arch);
+    let backend = match arch {
+        "aarch64" | "aarch64_be" | "aarch64_32" | "arm64" | "arm64e" | "arm64ec" | "arm64_32" => {
+            Backend::AArch64
+        }
+        "amdgcn" | "amdgpu" | "r600" => Backend::AMDGPU,
+        "arc" => Backend::ARC,
+        "avr" => Backend::AVR,
+        "bpf" | "bpfel" | "bpfeb" => Backend::BPF,
+        "csky" => Backend::CSKY,
+        "dxil" => Backend::DirectX,
+        "hexagon" => Backend::Hexagon,
+        "lanai" => Backend::Lanai,
+        "loongarch32" | "loongarch64" => Backend::LoongArch,
+        "m68k" => Backend::M68k,
+        "msp430" => Backend::MSP430,
+        "nvptx" | "nvptx64" => Backend::NVPTX,
+        "s390x" | "systemz" => Backend::SystemZ,
+        "ve" => Backend::VE,
+        "wasm32" | "wasm64" => Backend::WebAssembly,
+        "x86" | "x86_64" | "i386" | "i486" | "i586" | "i686" => Backend::X86,
+        "xcore" => Backend::XCore,
+        "xtensa" => Backend::Xtensa,
+        // armv7, armebv7r, thumbv7em, ...
+        arch if arch.starts_with("arm") || arch.starts_with("thumb") => Backend::ARM,
+        // mipsel, mips64el, mipsisa32r6, ...
+        arch if arch.starts_with("mips") => Backend::Mips,
+        // powerpc64le, ppc32, ...
+        arch if arch.starts_with("powerpc") || arch.starts_with("ppc") => Backend::PowerPC,
+        // riscv32imac, riscv64gc, ...
+        arch if arch.starts_with("riscv") => Backend::RISCV,
+        // sparc64, sparcv9, sparcel, ...
+        arch if arch.starts_with("sparc") => Backend::Sparc,
+        arch if arch.starts_with("spirv") => Backend::SPIRV,
+        arch => return Err(InitError::UnknownArch(arch.to_owned())),
+    };
+    Ok(backend)
+}
+
+/// Returns the backend for the native architecture, falling back to the
+/// loaded LLVM's default target triple if the compile-time architecture is not
+/// recognised.
+fn get_native_backend() -> Option<Backend> {
+    static BACKEND: OnceLock<Option<Backend>> = OnceLock::new();
+    *BACKEND.get_or_init(|| {
+        arch2backend(get_native_arch())
+            .or_else(|err| {
+                let arch = get_default_triple_arch().ok_or(err)?;
+                arch2backend(&arch)
+            })
+            .ok()
+    })
+}
+
+unsafe fn init_all(component: Component) {
+    // A library that cannot be loaded has nothing to initialize.
+    let Ok(backends) = enabled_backends() else {
+        return;
+    };
+    for backend in backends {
+        // Not every backend implements every component.
+        let _: Result<(), InitError> = init_entry_point(backend, component);
+    }
 }
 
 #[no_mangle]
 pub unsafe extern "C" fn LLVM_InitializeAllTargetInfos() {
-    init_all("TargetInfo");
+    init_all(Component::TargetInfo);
 }
 #[no_mangle]
 pub unsafe extern "C" fn LLVM_InitializeAllTargets() {
-    init_all("Target");
+    init_all(Component::Target);
 }
 #[no_mangle]
 pub unsafe extern "C" fn LLVM_InitializeAllTargetMCs() {
-    init_all("TargetMC");
+    init_all(Component::TargetMC);
 }
 #[no_mangle]
 pub unsafe extern "C" fn LLVM_InitializeAllAsmParsers() {
-    init_all("AsmParser");
+    init_all(Component::AsmParser);
 }
 #[no_mangle]
 pub unsafe extern "C" fn LLVM_InitializeAllAsmPrinters() {
-    init_all("AsmPrinter");
+    init_all(Component::AsmPrinter);
+}
+#[no_mangle]
+pub unsafe extern "C" fn LLVM_InitializeAllDisassemblers() {
+    init_all(Component::Disassembler);
 }
 
-unsafe fn init_native(postfix: &str) -> LLVMBool {
-    let backend = get_native_backend();
-    let name = format!("LLVMInitialize{backend}{postfix}");
-    if let Ok(entrypoint) = SHARED_LIB.get::<unsafe extern "C" fn()>(name.as_bytes()) {
-        entrypoint();
-        0
-    } else {
-        1
+unsafe fn init_native(component: Component) -> LLVMBool {
+    let Some(backend) = get_native_backend() else {
+        return 1;
+    };
+    match initialize(backend, component) {
+        Ok(()) => 0,
+        Err(_) => 1,
     }
 }
 
 #[no_mangle]
 pub unsafe extern "C" fn LLVM_InitializeNativeTarget() -> LLVMBool {
-    init_native("Target")
+    init_native(Component::Target)
 }
 #[no_mangle]
 pub unsafe extern "C" fn LLVM_InitializeNativeAsmParser() -> LLVMBool {
-    init_native("AsmParser")
+    init_native(Component::AsmParser)
 }
 #[no_mangle]
 pub unsafe extern "C" fn LLVM_InitializeNativeAsmPrinter() -> LLVMBool {
-    init_native("AsmPrinter")
+    init_native(Component::AsmPrinter)
 }
 #[no_mangle]
 pub unsafe extern "C" fn LLVM_InitializeNativeDisassembler() -> LLVMBool {
-    init_native("Disassembler")
+    init_native(Component::Disassembler)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{arch2backend, Backend, InitError};
+
+    #[test]
+    fn arch_to_backend() {
+        for (arch, backend) in [
+            // rustc architectures and triples.
+            ("riscv64", Backend::RISCV),
+            ("riscv32imac-unknown-none-elf", Backend::RISCV),
+            ("loongarch64", Backend::LoongArch),
+            ("s390x", Backend::SystemZ),
+            ("powerpc64le", Backend::PowerPC),
+            ("wasm32", Backend::WebAssembly),
+            ("bpf", Backend::BPF),
+            ("bpfel-unknown-none", Backend::BPF),
+            ("csky", Backend::CSKY),
+            ("m68k", Backend::M68k),
+            ("hexagon", Backend::Hexagon),
+            ("x86_64", Backend::X86),
+            ("armv7-unknown-linux-gnueabihf", Backend::ARM),
+            // LLVM target names, as returned by the target registry.
+            ("x86-64", Backend::X86),
+            ("ppc64le", Backend::PowerPC),
+            ("aarch64_32", Backend::AArch64),
+            ("thumb", Backend::ARM),
+            ("systemz", Backend::SystemZ),
+        ] {
+            assert_eq!(arch2backend(arch).ok(), Some(backend), "{arch}");
+        }
+        assert!(matches!(
+            arch2backend("z80"),
+            Err(InitError::UnknownArch(arch)) if arch == "z80"
+        ));
+    }
 }
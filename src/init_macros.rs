@@ -0,0 +1,17 @@
+// The target initialization wrappers llvm-sys declares (see
+// llvm-sys/wrappers/target.c), which `init` exports in their place.
+//
+// This is the only list of them: build.rs includes it to skip them when
+// generating `proxy`, and tests/init.rs to check that `init` exports each one.
+init_macros! {
+    LLVM_InitializeAllTargetInfos,
+    LLVM_InitializeAllTargets,
+    LLVM_InitializeAllTargetMCs,
+    LLVM_InitializeAllAsmPrinters,
+    LLVM_InitializeAllAsmParsers,
+    LLVM_InitializeAllDisassemblers,
+    LLVM_InitializeNativeTarget,
+    LLVM_InitializeNativeAsmParser,
+    LLVM_InitializeNativeAsmPrinter,
+    LLVM_InitializeNativeDisassembler,
+}
@@ -38,73 +38,166 @@
 //! ``` rust
 //! extern crate aya_rustc_llvm_proxy;
 //! ```
+//!
+//! ## Library discovery
+//! The LLVM shared library is looked up in `LD_LIBRARY_PATH`,
+//! `DYLD_FALLBACK_LIBRARY_PATH`, the sysroot of the Rust toolchain reported by
+//! `rustc --print sysroot` (or `$RUSTC --print sysroot`), the directory of the
+//! running executable, the `lib` directory next to it and its `DT_RUNPATH`, and
+//! the `lib` directories next to `PATH` entries. Candidates must be ELF shared
+//! objects exporting [`SENTINEL_SYMBOL`]; when several are found, they are
+//! ranked as described in [`Candidate::rank`]. To pin a specific library, set
+//! `RUSTC_LLVM_PROXY_LIB` to its full path; the search is then skipped
+//! entirely.
+//!
+//! Toolchains that link LLVM statically into `librustc_driver` ship no LLVM
+//! shared library. The driver found in the sysroot is then used instead if it
+//! exports the LLVM C API; see [`provider`].
+//!
+//! Libraries whose LLVM major version differs from the one `llvm-sys` was built
+//! for ([`LLVM_SYS_MAJOR`]) are skipped, since calling into them would break
+//! the ABI silently. See [`VersionPolicy`] to relax this.
+//!
+//! Applications can also pick the library themselves with a [`Loader`] before
+//! the first proxied call:
+//!
+//! ``` no_run
+//! aya_rustc_llvm_proxy::Loader::new()
+//!     .search_dir("/opt/rust/lib")
+//!     .load()
+//!     .expect("unable to load LLVM");
+//! ```
 
 use libloading::Library;
+use std::path::Path;
+use std::sync::OnceLock;
 
+#[macro_use]
+mod diagnostics;
+mod discovery;
+mod elf;
 pub mod init;
+mod loader;
+mod symbol;
+mod version;
 
-static SHARED_LIB: std::sync::LazyLock<Library> = std::sync::LazyLock::new(|| {
-    for (var, is_bin) in [
-        ("LD_LIBRARY_PATH", false),
-        ("DYLD_FALLBACK_LIBRARY_PATH", false),
-        ("PATH", true),
-    ] {
-        let Some(unparsed) = std::env::var_os(var) else {
-            continue;
-        };
-        let paths = std::env::split_paths(&unparsed);
-        for mut path in paths {
-            if is_bin {
-                path.pop();
-                path.push("lib");
-            }
-            let files = match path.read_dir() {
-                Ok(files) => files,
-                Err(err) => {
-                    eprintln!("unable to read dir {}: {}", path.display(), err);
-                    continue;
-                }
-            };
-            for (i, file) in files.enumerate() {
-                let file = match file {
-                    Ok(file) => file,
-                    Err(err) => {
-                        eprintln!(
-                            "unable to read dir entry {} in {}: {}",
-                            i,
-                            path.display(),
-                            err
-                        );
-                        continue;
-                    }
-                };
-                let path = file.path();
-                let Some(stem) = path.file_stem() else {
-                    continue;
-                };
-                let Some(stem) = stem.to_str() else { continue };
-                if stem.starts_with("libLLVM") {
-                    match unsafe { Library::new(&path) } {
-                        Ok(library) => return library,
-                        Err(error) => {
-                            eprintln!(
-                                "unable to open LLVM shared lib {}: {}",
-                                path.display(),
-                                error
-                            );
-                            continue;
-                        }
-                    }
-                }
-            }
-        }
+pub use discovery::{
+    Candidate, Choice, ChoiceReason, DiscoveryReport, Provider, SearchSource, SearchedDir,
+    SENTINEL_SYMBOL,
+};
+pub use loader::{
+    LoadError, Loader, RejectReason, RejectedCandidate, VersionPolicy, BACKENDS_ENV_VAR,
+    LIB_ENV_VAR, VERSION_POLICY_ENV_VAR,
+};
+pub use symbol::{set_missing_symbol_handler, MissingSymbolHandler};
+pub use version::{Version, LLVM_SYS_MAJOR, LLVM_SYS_VERSION};
+
+static SHARED_LIB: OnceLock<loader::LoadedLibrary> = OnceLock::new();
+
+/// Returns the loaded LLVM shared library, loading it with the default
+/// [`Loader`] if no library has been installed yet.
+///
+/// Proxied calls panic when loading fails; call this first to handle the
+/// error instead. A failure is returned as [`LoadError::Cached`], and later
+/// calls return it without searching again; use [`LoadError::root`] to match
+/// on the underlying error.
+pub fn try_load() -> Result<&'static Library, LoadError> {
+    loader::default_library().map(|library| &library.library)
+}
+
+/// Returns the version of the loaded LLVM shared library, loading it if
+/// needed.
+///
+/// Returns `None` if the library cannot be loaded or does not report its
+/// version. Compare with [`LLVM_SYS_VERSION`], the `llvm-sys` version this
+/// crate was built against.
+pub fn llvm_version() -> Option<Version> {
+    loader::default_library().ok()?.version
+}
+
+/// Returns the path of the loaded LLVM shared library, loading it if needed.
+///
+/// Returns `None` if the library cannot be loaded.
+pub fn library_path() -> Option<&'static Path> {
+    loader::default_library()
+        .ok()
+        .map(|library| library.path.as_path())
+}
+
+/// Returns the kind of library providing the LLVM C API, loading it if
+/// needed.
+///
+/// Returns `None` if the library cannot be loaded.
+pub fn provider() -> Option<Provider> {
+    loader::default_library()
+        .ok()
+        .map(|library| library.provider)
+}
+
+/// Lists every directory searched and every candidate library found by the
+/// default [`Loader`], and which candidate it would choose, without loading
+/// anything.
+///
+/// ``` no_run
+/// println!("{}", aya_rustc_llvm_proxy::discover());
+/// ```
+pub fn discover() -> DiscoveryReport {
+    Loader::new().discover()
+}
+
+fn loaded_library() -> &'static loader::LoadedLibrary {
+    match loader::default_library() {
+        Ok(library) => library,
+        Err(err) => panic!("{err}"),
     }
-    panic!("unable to find LLVM shared lib")
-});
+}
+
+fn shared_lib() -> &'static Library {
+    &loaded_library().library
+}
 
 /// LLVM C-API symbols with dynamic resolving.
 pub mod proxy {
-    use super::SHARED_LIB;
+    pub use super::symbol::{ProxySymbol, SymbolReport};
+    use super::{symbol, try_load, LoadError};
 
     include!(concat!(env!("OUT_DIR"), "/llvm_gen.rs"));
+
+    /// Resolves every proxied symbol now, loading the library if needed.
+    ///
+    /// Proxied calls normally resolve their symbol on first use and panic if
+    /// it is missing. Calling this at startup reports every missing symbol
+    /// before any work has been done.
+    ///
+    /// Functions llvm-sys declares that libLLVM never exports, such as the
+    /// libLTO API (`lto_*`) and functions removed from the targeted LLVM
+    /// release, are reported in
+    /// [`missing_unavailable`](SymbolReport::missing_unavailable) rather than
+    /// [`missing`](SymbolReport::missing), so a library matching
+    /// [`LLVM_SYS_MAJOR`] yields a complete report.
+    ///
+    /// [`LLVM_SYS_MAJOR`]: crate::LLVM_SYS_MAJOR
+    pub fn resolve_all() -> Result<SymbolReport, LoadError> {
+        try_load()?;
+        Ok(symbol::resolve_all(
+            SYMBOLS,
+            LAZY_SYMBOLS,
+            UNAVAILABLE_SYMBOLS,
+        ))
+    }
+
+    /// Returns true if the loaded library exports `name`, loading the
+    /// library if needed.
+    ///
+    /// Use this to guard calls to functions that only exist in some LLVM
+    /// releases. Returns false if the library cannot be loaded.
+    pub fn available(name: &str) -> bool {
+        let Ok(library) = try_load() else {
+            return false;
+        };
+        match SYMBOLS.binary_search_by_key(&name, |ProxySymbol { name, .. }| name) {
+            Ok(index) => LAZY_SYMBOLS[index].get().is_ok(),
+            Err(_) => unsafe { library.get::<*mut std::ffi::c_void>(name.as_bytes()) }.is_ok(),
+        }
+    }
 }
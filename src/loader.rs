@@ -0,0 +1,571 @@
+use super::discovery::{
+    self, Candidate, Choice, ChoiceReason, DiscoveryReport, Provider, SearchSource, SearchedDir,
+};
+use super::init::Backend;
+use super::version::{self, Version, LLVM_SYS_MAJOR};
+use super::SHARED_LIB;
+use libloading::Library;
+
+use std::fmt;
+use std::io;
+use std::path::PathBuf;
+use std::sync::{Mutex, OnceLock, PoisonError};
+
+/// Environment variable naming the exact LLVM shared library to load.
+///
+/// When set, the library search is skipped entirely and failing to open the
+/// named file is fatal.
+pub const LIB_ENV_VAR: &str = "RUSTC_LLVM_PROXY_LIB";
+
+/// Environment variable selecting the [`VersionPolicy`]: `strict`, `warn` or
+/// `ignore`. Other values are ignored with a warning, printed to stderr
+/// unless the `log` or `tracing` feature is enabled.
+pub const VERSION_POLICY_ENV_VAR: &str = "RUSTC_LLVM_PROXY_VERSION_POLICY";
+
+/// Environment variable restricting the backends initialized by the
+/// `LLVM_InitializeAll*` functions, as a comma-separated list such as
+/// `BPF,X86`. Names are matched against [`Backend`](crate::init::Backend)
+/// ignoring case; unknown names are skipped with a warning, printed to stderr
+/// unless the `log` or `tracing` feature is enabled.
+pub const BACKENDS_ENV_VAR: &str = "RUSTC_LLVM_PROXY_BACKENDS";
+
+/// Serializes loading so that only one library is ever installed.
+static LOAD_LOCK: Mutex<()> = Mutex::new(());
+
+/// The error from the first failed load with the default configuration.
+static DEFAULT_LOAD_ERROR: OnceLock<LoadError> = OnceLock::new();
+
+/// Selects and installs the LLVM shared library used by the proxy.
+///
+/// The library is normally loaded implicitly on the first proxied call. A
+/// `Loader` lets an application pick it explicitly beforehand:
+///
+/// ``` no_run
+/// aya_rustc_llvm_proxy::Loader::new()
+///     .path("/opt/llvm/lib/libLLVM.so.21.1")
+///     .load()
+///     .expect("unable to load LLVM");
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct Loader {
+    path: Option<PathBuf>,
+    search_dirs: Vec<PathBuf>,
+    version_policy: Option<VersionPolicy>,
+    backends: Option<Vec<Backend>>,
+}
+
+/// What to do when a library's LLVM major version differs from
+/// [`LLVM_SYS_MAJOR`](crate::LLVM_SYS_MAJOR).
+///
+/// Calling into a library with a different major version breaks the ABI
+/// silently, so mismatches are rejected by default.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum VersionPolicy {
+    /// Skip mismatched candidates; fail if an explicitly named library
+    /// mismatches.
+    #[default]
+    Strict,
+    /// Prefer matching candidates, but fall back to a mismatched one with a
+    /// warning.
+    Warn,
+    /// Accept any version.
+    Ignore,
+}
+
+impl VersionPolicy {
+    fn from_env() -> Option<Self> {
+        let policy = std::env::var(VERSION_POLICY_ENV_VAR).ok()?;
+        match policy.as_str() {
+            "strict" => Some(Self::Strict),
+            "warn" => Some(Self::Warn),
+            "ignore" => Some(Self::Ignore),
+            policy => {
+                // A typo would otherwise go unnoticed without a logger.
+                #[cfg(not(any(feature = "log", feature = "tracing")))]
+                eprintln!("warning: ignoring unknown {VERSION_POLICY_ENV_VAR} value {policy:?}");
+                diag!(
+                    warn,
+                    "ignoring unknown version policy",
+                    var = VERSION_POLICY_ENV_VAR,
+                    value = policy
+                );
+                None
+            }
+        }
+    }
+}
+
+/// Parses [`BACKENDS_ENV_VAR`], skipping names that are not backends.
+fn backends_from_env() -> Option<Vec<Backend>> {
+    let backends = std::env::var(BACKENDS_ENV_VAR).ok()?;
+    Some(
+        backends
+            .split(',')
+            .map(str::trim)
+            .filter(|name| !name.is_empty())
+            .filter_map(|name| {
+                let backend = Backend::from_name(name);
+                if backend.is_none() {
+                    // A typo would otherwise go unnoticed without a logger.
+                    #[cfg(not(any(feature = "log", feature = "tracing")))]
+                    eprintln!("warning: ignoring unknown backend {name:?} in {BACKENDS_ENV_VAR}");
+                    diag!(
+                        warn,
+                        "ignoring unknown backend",
+                        var = BACKENDS_ENV_VAR,
+                        name = name
+                    );
+                }
+                backend
+            })
+            .collect(),
+    )
+}
+
+/// The library installed for proxied calls.
+pub(crate) struct LoadedLibrary {
+    pub(crate) library: Library,
+    pub(crate) path: PathBuf,
+    pub(crate) version: Option<Version>,
+    pub(crate) provider: Provider,
+    /// The backends `LLVM_InitializeAll*` is restricted to.
+    pub(crate) backends: Option<Vec<Backend>>,
+}
+
+impl LoadedLibrary {
+    fn open(path: PathBuf, provider: Provider) -> Result<Self, libloading::Error> {
+        let library = unsafe { Library::new(&path) }?;
+        let version = version::library_version(&library);
+        Ok(Self {
+            library,
+            path,
+            version,
+            provider,
+            backends: None,
+        })
+    }
+
+    fn version_matches(&self) -> bool {
+        self.version
+            .is_some_and(|Version { major, .. }| major == LLVM_SYS_MAJOR)
+    }
+}
+
+/// Errors returned when loading the LLVM shared library.
+#[derive(Debug)]
+pub enum LoadError {
+    /// A library has already been loaded, explicitly or by a proxied call.
+    AlreadyLoaded,
+    /// The library at `path` could not be opened.
+    Open {
+        path: PathBuf,
+        source: libloading::Error,
+    },
+    /// The library at `path` has a different LLVM major version.
+    ///
+    /// `found` is `None` when the library does not report its version.
+    VersionMismatch {
+        path: PathBuf,
+        expected: u32,
+        found: Option<Version>,
+    },
+    /// No LLVM shared library was found in any of the searched directories.
+    NotFound {
+        /// Every directory that was searched, in order.
+        searched: Vec<SearchedDir>,
+        /// Every candidate that was considered and rejected, in order.
+        rejected: Vec<RejectedCandidate>,
+    },
+    /// Loading with the default configuration already failed with this error.
+    ///
+    /// The search is not repeated; a [`Loader`] can still load a library.
+    Cached(&'static LoadError),
+}
+
+impl LoadError {
+    /// Returns the error itself, or for [`LoadError::Cached`] the error it
+    /// wraps.
+    ///
+    /// [`try_load`](crate::try_load) always returns failures as `Cached`, so
+    /// match on this to inspect them:
+    ///
+    /// ``` no_run
+    /// use aya_rustc_llvm_proxy::LoadError;
+    ///
+    /// if let Err(err) = aya_rustc_llvm_proxy::try_load() {
+    ///     if let LoadError::NotFound { searched, .. } = err.root() {
+    ///         eprintln!("no LLVM shared lib in {} directories", searched.len());
+    ///     }
+    /// }
+    /// ```
+    pub fn root(&self) -> &LoadError {
+        match self {
+            Self::Cached(error) => error.root(),
+            error => error,
+        }
+    }
+}
+
+/// A file that looked like the LLVM shared library but was not used.
+#[derive(Debug)]
+pub struct RejectedCandidate {
+    pub path: PathBuf,
+    pub reason: RejectReason,
+}
+
+/// Why a candidate library was rejected.
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum RejectReason {
+    /// The file could not be opened as a shared library.
+    Open(libloading::Error),
+    /// The file could not be read while checking it.
+    Read(io::Error),
+    /// The file is not an ELF shared object, e.g. a static archive.
+    NotSharedObject,
+    /// The library does not export the named symbol, so it is not the whole
+    /// LLVM C API; e.g. a component library from a split build.
+    MissingSymbol(&'static str),
+    /// The library has a different LLVM major version.
+    ///
+    /// When the mismatch is detected from the file name, the library is not
+    /// opened and `found` carries the version parsed from the name.
+    VersionMismatch {
+        expected: u32,
+        found: Option<Version>,
+    },
+}
+
+fn fmt_found(found: &Option<Version>) -> String {
+    match found {
+        Some(found) => found.to_string(),
+        None => "an unknown version".to_owned(),
+    }
+}
+
+impl fmt::Display for RejectReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Open(error) => write!(f, "unable to open: {error}"),
+            Self::Read(error) => write!(f, "unable to read: {error}"),
+            Self::NotSharedObject => write!(f, "not an ELF shared object"),
+            Self::MissingSymbol(name) => write!(f, "does not export {name}"),
+            Self::VersionMismatch { expected, found } => {
+                write!(f, "expected LLVM {expected}, found {}", fmt_found(found))
+            }
+        }
+    }
+}
+
+impl fmt::Display for LoadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::AlreadyLoaded => write!(f, "LLVM shared lib is already loaded"),
+            Self::Open { path, source } => write!(
+                f,
+                "unable to open LLVM shared lib {}: {}",
+                path.display(),
+                source
+            ),
+            Self::VersionMismatch {
+                path,
+                expected,
+                found,
+            } => write!(
+                f,
+                "LLVM shared lib {} is {}, expected LLVM {}",
+                path.display(),
+                fmt_found(found),
+                expected
+            ),
+            Self::NotFound { searched, rejected } => {
+                write!(f, "unable to find LLVM shared lib")?;
+                for SearchedDir { path, error, .. } in searched {
+                    match error {
+                        Some(error) => write!(f, "\n  searched {}: {}", path.display(), error)?,
+                        None => write!(f, "\n  searched {}", path.display())?,
+                    }
+                }
+                for RejectedCandidate { path, reason } in rejected {
+                    write!(f, "\n  rejected {}: {}", path.display(), reason)?;
+                }
+                Ok(())
+            }
+            Self::Cached(error) => error.fmt(f),
+        }
+    }
+}
+
+impl std::error::Error for LoadError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Open { source, .. } => Some(source),
+            Self::Cached(error) => error.source(),
+            Self::AlreadyLoaded | Self::VersionMismatch { .. } | Self::NotFound { .. } => None,
+        }
+    }
+}
+
+impl Loader {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Loads exactly the library at `path`, skipping the search.
+    ///
+    /// This takes precedence over [`LIB_ENV_VAR`].
+    pub fn path(mut self, path: impl Into<PathBuf>) -> Self {
+        self.path = Some(path.into());
+        self
+    }
+
+    /// Adds a directory to search before the default locations.
+    ///
+    /// Directories are searched in the order they are added.
+    pub fn search_dir(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.search_dirs.push(dir.into());
+        self
+    }
+
+    /// Sets how LLVM version mismatches are handled.
+    ///
+    /// This takes precedence over [`VERSION_POLICY_ENV_VAR`].
+    pub fn version_policy(mut self, policy: VersionPolicy) -> Self {
+        self.version_policy = Some(policy);
+        self
+    }
+
+    /// Restricts the backends initialized by the `LLVM_InitializeAll*`
+    /// functions.
+    ///
+    /// This takes precedence over [`BACKENDS_ENV_VAR`].
+    pub fn backends(mut self, backends: impl IntoIterator<Item = Backend>) -> Self {
+        self.backends = Some(backends.into_iter().collect());
+        self
+    }
+
+    /// Loads the library and installs it for all proxied calls.
+    ///
+    /// Fails with [`LoadError::AlreadyLoaded`] if a library has already been
+    /// loaded.
+    pub fn load(self) -> Result<&'static Library, LoadError> {
+        let _guard = LOAD_LOCK.lock().unwrap_or_else(PoisonError::into_inner);
+        if SHARED_LIB.get().is_some() {
+            return Err(LoadError::AlreadyLoaded);
+        }
+        let library = self.open()?;
+        Ok(&SHARED_LIB.get_or_init(|| library).library)
+    }
+
+    /// Returns the installed library, or loads one with this configuration.
+    ///
+    /// Unlike [`Loader::load`], this succeeds if a library has already been
+    /// loaded, in which case the configuration is ignored.
+    pub fn get_or_load(self) -> Result<&'static Library, LoadError> {
+        self.get_or_load_library()
+            .map(|LoadedLibrary { library, .. }| library)
+    }
+
+    fn get_or_load_library(self) -> Result<&'static LoadedLibrary, LoadError> {
+        if let Some(library) = SHARED_LIB.get() {
+            return Ok(library);
+        }
+        let _guard = LOAD_LOCK.lock().unwrap_or_else(PoisonError::into_inner);
+        if let Some(library) = SHARED_LIB.get() {
+            return Ok(library);
+        }
+        let library = self.open()?;
+        Ok(SHARED_LIB.get_or_init(|| library))
+    }
+
+    fn open(mut self) -> Result<LoadedLibrary, LoadError> {
+        let backends = self.backends.take().or_else(backends_from_env);
+        let mut library = self.find()?;
+        library.backends = backends;
+        Ok(library)
+    }
+
+    /// Lists the candidate libraries for this configuration without loading
+    /// any of them.
+    pub fn discover(&self) -> DiscoveryReport {
+        let path = self
+            .path
+            .clone()
+            .map(|path| (path, SearchSource::LoaderPath))
+            .or_else(|| {
+                std::env::var_os(LIB_ENV_VAR).map(|path| (path.into(), SearchSource::LibEnvVar))
+            });
+        discovery::discover(path, self.search_dirs.clone(), self.policy())
+    }
+
+    fn policy(&self) -> VersionPolicy {
+        self.version_policy
+            .or_else(VersionPolicy::from_env)
+            .unwrap_or_default()
+    }
+
+    fn find(self) -> Result<LoadedLibrary, LoadError> {
+        let policy = self.policy();
+        let DiscoveryReport {
+            searched,
+            mut candidates,
+            mut rejected,
+            choice,
+        } = self.discover();
+        if let Some(Choice {
+            index,
+            reason: ChoiceReason::Explicit,
+        }) = choice
+        {
+            let Candidate { path, provider, .. } = candidates.swap_remove(index);
+            let library = match LoadedLibrary::open(path.clone(), provider) {
+                Ok(library) => library,
+                Err(source) => return Err(LoadError::Open { path, source }),
+            };
+            if !library.version_matches() {
+                match policy {
+                    VersionPolicy::Strict => {
+                        return Err(LoadError::VersionMismatch {
+                            path,
+                            expected: LLVM_SYS_MAJOR,
+                            found: library.version,
+                        })
+                    }
+                    VersionPolicy::Warn => warn_mismatch(&library),
+                    VersionPolicy::Ignore => {}
+                }
+            }
+            accept(&library);
+            return Ok(library);
+        }
+        // The first mismatched library, used under `VersionPolicy::Warn` when
+        // nothing matches.
+        let mut fallback = None;
+        // Candidates whose file name mismatches, tried last under
+        // `VersionPolicy::Warn`.
+        let mut deferred = Vec::new();
+        for Candidate {
+            path,
+            version,
+            provider,
+            ..
+        } in candidates
+        {
+            if policy != VersionPolicy::Ignore && discovery::name_mismatches(&version) {
+                // Avoid opening libraries that are known to mismatch.
+                if policy == VersionPolicy::Warn {
+                    deferred.push((path.clone(), provider));
+                }
+                reject(
+                    &mut rejected,
+                    path,
+                    RejectReason::VersionMismatch {
+                        expected: LLVM_SYS_MAJOR,
+                        found: version,
+                    },
+                );
+                continue;
+            }
+            let library = match LoadedLibrary::open(path.clone(), provider) {
+                Ok(library) => library,
+                Err(error) => {
+                    reject(&mut rejected, path, RejectReason::Open(error));
+                    continue;
+                }
+            };
+            if policy == VersionPolicy::Ignore || library.version_matches() {
+                accept(&library);
+                return Ok(library);
+            }
+            reject(
+                &mut rejected,
+                path,
+                RejectReason::VersionMismatch {
+                    expected: LLVM_SYS_MAJOR,
+                    found: library.version,
+                },
+            );
+            if policy == VersionPolicy::Warn && fallback.is_none() {
+                fallback = Some(library);
+            }
+        }
+        let fallback = fallback.or_else(|| {
+            deferred
+                .into_iter()
+                .find_map(|(path, provider)| LoadedLibrary::open(path, provider).ok())
+        });
+        if let Some(library) = fallback {
+            // A deferred library may match once loaded despite its name.
+            if !library.version_matches() {
+                warn_mismatch(&library);
+            }
+            return Ok(library);
+        }
+        Err(LoadError::NotFound { searched, rejected })
+    }
+}
+
+/// Returns the installed library, or loads one with the default
+/// configuration.
+///
+/// A failure is remembered and returned as [`LoadError::Cached`] from then on,
+/// so that proxied calls made after a failed load do not repeat the search.
+pub(crate) fn default_library() -> Result<&'static LoadedLibrary, LoadError> {
+    if let Some(library) = SHARED_LIB.get() {
+        return Ok(library);
+    }
+    if let Some(error) = DEFAULT_LOAD_ERROR.get() {
+        return Err(LoadError::Cached(error));
+    }
+    let _guard = LOAD_LOCK.lock().unwrap_or_else(PoisonError::into_inner);
+    if let Some(library) = SHARED_LIB.get() {
+        return Ok(library);
+    }
+    if let Some(error) = DEFAULT_LOAD_ERROR.get() {
+        return Err(LoadError::Cached(error));
+    }
+    match Loader::new().open() {
+        Ok(library) => Ok(SHARED_LIB.get_or_init(|| library)),
+        Err(error) => Err(LoadError::Cached(DEFAULT_LOAD_ERROR.get_or_init(|| error))),
+    }
+}
+
+fn accept(library: &LoadedLibrary) {
+    diag!(
+        info,
+        "considered LLVM shared lib",
+        candidate = library.path.display(),
+        provider = library.provider,
+        accepted = true,
+        version = fmt_found(&library.version)
+    );
+}
+
+pub(crate) fn reject(rejected: &mut Vec<RejectedCandidate>, path: PathBuf, reason: RejectReason) {
+    diag!(
+        debug,
+        "considered LLVM shared lib",
+        candidate = path.display(),
+        accepted = false,
+        reason = reason
+    );
+    rejected.push(RejectedCandidate { path, reason });
+}
+
+fn warn_mismatch(library: &LoadedLibrary) {
+    // The caller asked to be warned, so say so even without a logger.
+    #[cfg(not(any(feature = "log", feature = "tracing")))]
+    eprintln!(
+        "warning: LLVM shared lib {} is {}, expected LLVM {}",
+        library.path.display(),
+        fmt_found(&library.version),
+        LLVM_SYS_MAJOR
+    );
+    diag!(
+        warn,
+        "using LLVM shared lib with mismatched version",
+        candidate = library.path.display(),
+        found = fmt_found(&library.version),
+        expected = LLVM_SYS_MAJOR
+    );
+}
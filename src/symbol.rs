@@ -0,0 +1,167 @@
+use super::loader::LoadedLibrary;
+use super::{shared_lib, SHARED_LIB};
+
+use std::ffi::c_void;
+use std::ptr;
+use std::sync::atomic::{AtomicPtr, Ordering};
+use std::sync::{PoisonError, RwLock};
+
+/// Called with the name of a symbol the loaded library does not export.
+pub type MissingSymbolHandler = fn(&'static str) -> !;
+
+static MISSING_SYMBOL_HANDLER: RwLock<Option<MissingSymbolHandler>> = RwLock::new(None);
+
+/// Installs a process-wide handler for proxied calls whose symbol is missing
+/// from the loaded library, replacing any previous handler.
+///
+/// Without a handler, the behaviour is chosen when building this crate through
+/// the `RUSTC_LLVM_PROXY_MISSING_SYMBOL` environment variable:
+///
+/// * `panic` (the default) panics with the symbol, library path and version,
+///   running the panic hook; the panic cannot unwind out of the `extern "C"`
+///   proxy, so the process then aborts,
+/// * `abort` prints the same message and aborts without panicking,
+/// * `hook` requires a handler and aborts if none was installed.
+pub fn set_missing_symbol_handler(handler: MissingSymbolHandler) {
+    *MISSING_SYMBOL_HANDLER
+        .write()
+        .unwrap_or_else(PoisonError::into_inner) = Some(handler);
+}
+
+#[cold]
+fn missing_symbol(name: &'static str) -> ! {
+    let handler = *MISSING_SYMBOL_HANDLER
+        .read()
+        .unwrap_or_else(PoisonError::into_inner);
+    if let Some(handler) = handler {
+        handler(name)
+    }
+    let message = match SHARED_LIB.get() {
+        Some(LoadedLibrary { path, version, .. }) => match version {
+            Some(version) => format!(
+                "LLVM shared lib {} (LLVM {}) does not export {}",
+                path.display(),
+                version,
+                name
+            ),
+            None => format!(
+                "LLVM shared lib {} (unknown LLVM version) does not export {}",
+                path.display(),
+                name
+            ),
+        },
+        None => format!("LLVM shared lib does not export {name}"),
+    };
+    if cfg!(rustc_llvm_proxy_missing_symbol = "panic") {
+        panic!("{message}")
+    }
+    if cfg!(rustc_llvm_proxy_missing_symbol = "hook") {
+        eprintln!("{message}, and no missing symbol handler is installed");
+    } else {
+        eprintln!("{message}");
+    }
+    std::process::abort()
+}
+
+/// A symbol in the LLVM shared library whose address is resolved on first use
+/// and cached afterwards.
+pub(crate) struct LazySymbol {
+    name: &'static str,
+    address: AtomicPtr<c_void>,
+}
+
+impl LazySymbol {
+    pub(crate) const fn new(name: &'static str) -> Self {
+        Self {
+            name,
+            address: AtomicPtr::new(ptr::null_mut()),
+        }
+    }
+
+    /// Returns the address of the symbol, resolving it on first use.
+    pub(crate) fn get(&self) -> Result<*mut c_void, libloading::Error> {
+        let Self { name, address } = self;
+        let cached = address.load(Ordering::Acquire);
+        if !cached.is_null() {
+            return Ok(cached);
+        }
+        let resolved = *unsafe { shared_lib().get::<*mut c_void>(name.as_bytes()) }?;
+        // Racing threads resolve the same address, so the last store wins
+        // harmlessly.
+        address.store(resolved, Ordering::Release);
+        Ok(resolved)
+    }
+
+    /// Returns the address of the symbol, handing a missing symbol to the
+    /// configured policy.
+    pub(crate) fn resolve(&self) -> *mut c_void {
+        match self.get() {
+            Ok(address) => address,
+            Err(_) => missing_symbol(self.name),
+        }
+    }
+}
+
+/// A function proxied by [`crate::proxy`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ProxySymbol {
+    /// The symbol name, e.g. `LLVMRunPasses`.
+    pub name: &'static str,
+    /// The `llvm_sys` module declaring it, e.g.
+    /// `llvm_sys::transforms::pass_builder`.
+    ///
+    /// Per-target initializers that llvm-sys does not declare, such as
+    /// `LLVMInitializeAVRTarget`, are listed under `llvm_sys::target`.
+    pub module: &'static str,
+}
+
+/// The outcome of [`resolve_all`](crate::proxy::resolve_all).
+#[derive(Clone, Debug, Default)]
+pub struct SymbolReport {
+    /// Symbols exported by the loaded library.
+    pub resolved: Vec<&'static str>,
+    /// Symbols the proxy declares but the loaded library does not export.
+    pub missing: Vec<&'static str>,
+    /// Per-target `LLVMInitialize*` functions the loaded library does not
+    /// export, because it was built without those backends.
+    ///
+    /// These are expected, and do not make the report incomplete; see
+    /// [`init::available_backends`](crate::init::available_backends).
+    pub missing_initializers: Vec<&'static str>,
+    /// Functions llvm-sys declares that libLLVM does not export in the LLVM
+    /// release llvm-sys targets, such as the libLTO API (`lto_*`) and
+    /// functions LLVM has removed, when the loaded library lacks them too.
+    ///
+    /// These are expected, and do not make the report incomplete.
+    pub missing_unavailable: Vec<&'static str>,
+}
+
+impl SymbolReport {
+    /// Returns true if every proxied symbol was resolved, other than the
+    /// initializers of backends the library was built without and the
+    /// functions libLLVM does not export.
+    pub fn is_complete(&self) -> bool {
+        self.missing.is_empty()
+    }
+}
+
+pub(crate) fn resolve_all(
+    symbols: &[ProxySymbol],
+    lazy_symbols: &[&LazySymbol],
+    unavailable: &[&str],
+) -> SymbolReport {
+    let mut report = SymbolReport::default();
+    for (ProxySymbol { name, module }, symbol) in symbols.iter().zip(lazy_symbols) {
+        match symbol.get() {
+            Ok(_) => report.resolved.push(name),
+            Err(_) if unavailable.binary_search(name).is_ok() => {
+                report.missing_unavailable.push(name)
+            }
+            Err(_) if *module == "llvm_sys::target" && name.starts_with("LLVMInitialize") => {
+                report.missing_initializers.push(name)
+            }
+            Err(_) => report.missing.push(name),
+        }
+    }
+    report
+}
@@ -0,0 +1,69 @@
+use libloading::Library;
+
+use std::ffi::c_uint;
+use std::fmt;
+use std::path::Path;
+
+include!(concat!(env!("OUT_DIR"), "/version_gen.rs"));
+
+/// An LLVM version.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Version {
+    pub major: u32,
+    pub minor: u32,
+    pub patch: u32,
+}
+
+impl fmt::Display for Version {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let Self {
+            major,
+            minor,
+            patch,
+        } = self;
+        write!(f, "{major}.{minor}.{patch}")
+    }
+}
+
+/// Queries the version of a loaded LLVM library.
+///
+/// Returns `None` when the library predates `LLVMGetVersion` (LLVM 16).
+pub(crate) fn library_version(library: &Library) -> Option<Version> {
+    let get_version = unsafe {
+        library
+            .get::<unsafe extern "C" fn(*mut c_uint, *mut c_uint, *mut c_uint)>(b"LLVMGetVersion")
+    }
+    .ok()?;
+    let (mut major, mut minor, mut patch) = (0, 0, 0);
+    unsafe { get_version(&mut major, &mut minor, &mut patch) };
+    Some(Version {
+        major,
+        minor,
+        patch,
+    })
+}
+
+/// Extracts the LLVM version from a library file name.
+///
+/// Understands both `libLLVM.so.21.1-rust-1.90.0-stable` and
+/// `libLLVM-21-rust-1.90.0-stable.so` style names. Components missing from the
+/// name are reported as zero.
+pub(crate) fn file_name_version(path: &Path) -> Option<Version> {
+    let name = path.file_name()?.to_str()?;
+    let rest = name.strip_prefix("libLLVM")?;
+    let rest = rest
+        .strip_prefix(".so.")
+        .or_else(|| rest.strip_prefix('-'))
+        .or_else(|| rest.strip_prefix('.'))?;
+    let end = rest
+        .find(|c: char| !c.is_ascii_digit() && c != '.')
+        .unwrap_or(rest.len());
+    let mut components = rest[..end].split('.').map(str::parse);
+    let major = components.next()?.ok()?;
+    let mut next = || components.next().and_then(Result::ok).unwrap_or(0);
+    Some(Version {
+        major,
+        minor: next(),
+        patch: next(),
+    })
+}
@@ -0,0 +1,20 @@
+extern crate aya_rustc_llvm_proxy;
+extern crate llvm_sys;
+
+use aya_rustc_llvm_proxy::init::{is_initialized, Backend, Component};
+use aya_rustc_llvm_proxy::Loader;
+
+#[test]
+fn initialize_all_restricted() {
+    Loader::new()
+        .backends([Backend::BPF])
+        .load()
+        .expect("unable to load LLVM");
+
+    unsafe {
+        llvm_sys::target::LLVM_InitializeAllTargetInfos();
+    }
+
+    assert!(is_initialized(Backend::BPF, Component::TargetInfo));
+    assert!(!is_initialized(Backend::X86, Component::TargetInfo));
+}
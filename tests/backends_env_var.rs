@@ -0,0 +1,45 @@
+extern crate aya_rustc_llvm_proxy;
+extern crate llvm_sys;
+
+use aya_rustc_llvm_proxy::init::{is_initialized, Backend, Component};
+use aya_rustc_llvm_proxy::BACKENDS_ENV_VAR;
+use std::process::{Command, Output};
+
+/// Set in the child process, which loads with `BACKENDS_ENV_VAR` set.
+const CHILD_ENV_VAR: &str = "RUSTC_LLVM_PROXY_TEST_CHILD";
+
+#[test]
+fn initialize_all_restricted_by_env_var() {
+    if std::env::var_os(CHILD_ENV_VAR).is_some() {
+        aya_rustc_llvm_proxy::try_load().expect("unable to load LLVM");
+
+        unsafe {
+            llvm_sys::target::LLVM_InitializeAllTargetInfos();
+        }
+
+        assert!(is_initialized(Backend::BPF, Component::TargetInfo));
+        assert!(!is_initialized(Backend::X86, Component::TargetInfo));
+        return;
+    }
+
+    let Output { status, stderr, .. } = Command::new(std::env::current_exe().unwrap())
+        .args([
+            "--exact",
+            "initialize_all_restricted_by_env_var",
+            "--nocapture",
+            "--test-threads=1",
+        ])
+        .env(CHILD_ENV_VAR, "1")
+        .env(BACKENDS_ENV_VAR, " bpf, NotABackend,")
+        .output()
+        .unwrap();
+    let stderr = String::from_utf8_lossy(&stderr);
+    assert!(status.success(), "{stderr}");
+    // Unknown names are skipped with a warning.
+    if cfg!(not(any(feature = "log", feature = "tracing"))) {
+        assert!(
+            stderr.contains("ignoring unknown backend \"NotABackend\""),
+            "{stderr}"
+        );
+    }
+}
@@ -0,0 +1,83 @@
+// Each test binary uses only some of these helpers.
+#![allow(dead_code)]
+
+use aya_rustc_llvm_proxy::{BACKENDS_ENV_VAR, LIB_ENV_VAR, VERSION_POLICY_ENV_VAR};
+use std::ops::Deref;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// Set in child processes to the mode the test should run in.
+pub const CHILD_ENV_VAR: &str = "RUSTC_LLVM_PROXY_TEST_CHILD";
+
+/// A directory under the system temp dir, removed when dropped so that a
+/// failing test does not leave it behind.
+pub struct TempDir(PathBuf);
+
+impl TempDir {
+    /// Creates a directory unique to `name` and this process.
+    pub fn new(name: &str) -> Self {
+        let path =
+            std::env::temp_dir().join(format!("rustc-llvm-proxy-{name}-{}", std::process::id()));
+        std::fs::create_dir_all(&path).unwrap();
+        Self(path)
+    }
+}
+
+impl Deref for TempDir {
+    type Target = Path;
+
+    fn deref(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _: Result<(), _> = std::fs::remove_dir_all(&self.0);
+    }
+}
+
+/// Returns a command running the test `name` of this binary again, with
+/// `CHILD_ENV_VAR` set to `mode`, in an environment where the default search
+/// finds no LLVM shared lib: `PATH` is empty, `RUSTC` does not exist, and the
+/// library search path and proxy variables are unset.
+pub fn isolated_child(name: &str, mode: &str) -> Command {
+    let mut command = Command::new(std::env::current_exe().unwrap());
+    command
+        .args(["--exact", name, "--nocapture", "--test-threads=1"])
+        .env(CHILD_ENV_VAR, mode)
+        .env("PATH", "")
+        .env("RUSTC", "/nonexistent/rustc")
+        .env_remove("LD_LIBRARY_PATH")
+        .env_remove("DYLD_FALLBACK_LIBRARY_PATH")
+        .env_remove(LIB_ENV_VAR)
+        .env_remove(VERSION_POLICY_ENV_VAR)
+        .env_remove(BACKENDS_ENV_VAR);
+    command
+}
+
+/// Returns the real path of the LLVM shared lib the default search chooses.
+pub fn llvm_library() -> PathBuf {
+    aya_rustc_llvm_proxy::discover()
+        .chosen()
+        .expect("no candidate")
+        .path
+        .canonicalize()
+        .unwrap()
+}
+
+/// Returns the path of the libc this process runs with, a shared object that
+/// is not LLVM.
+pub fn libc() -> PathBuf {
+    let maps = std::fs::read_to_string("/proc/self/maps").unwrap();
+    maps.lines()
+        .filter_map(|line| line.split_whitespace().nth(5))
+        .find(|path| {
+            Path::new(path)
+                .file_name()
+                .and_then(|name| name.to_str())
+                .is_some_and(|name| name.starts_with("libc.so") || name.starts_with("libc-"))
+        })
+        .expect("libc is not mapped")
+        .into()
+}
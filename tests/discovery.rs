@@ -0,0 +1,280 @@
+extern crate aya_rustc_llvm_proxy;
+
+mod common;
+
+use aya_rustc_llvm_proxy::{
+    Candidate, Choice, ChoiceReason, Loader, Provider, RejectReason, SearchSource, Version,
+    VersionPolicy,
+};
+use common::{isolated_child, libc, llvm_library, TempDir, CHILD_ENV_VAR};
+use std::cmp::Reverse;
+use std::collections::HashSet;
+
+#[test]
+fn discover_explicit() {
+    let report = Loader::new().path("/opt/llvm/libLLVM.so.21.1").discover();
+    assert!(report.searched.is_empty());
+    let chosen = report.chosen().unwrap();
+    assert_eq!(chosen.source, SearchSource::LoaderPath);
+    assert_eq!(chosen.version.unwrap().major, 21);
+    assert_eq!(chosen.provider, Provider::LibLlvm);
+    assert_eq!(report.choice.unwrap().reason, ChoiceReason::Explicit);
+}
+
+// Discovery loads nothing, so the chosen candidate is the one loaded next.
+#[test]
+fn discover_then_load() {
+    let report = aya_rustc_llvm_proxy::discover();
+    println!("{report}");
+    let chosen = report.chosen().expect("no candidate").path.clone();
+    assert!(report
+        .candidates
+        .iter()
+        .any(|candidate| candidate.path == chosen));
+    assert_eq!(aya_rustc_llvm_proxy::library_path().unwrap(), chosen);
+}
+
+#[test]
+fn discover_rejects_invalid() {
+    let major = aya_rustc_llvm_proxy::LLVM_SYS_MAJOR;
+    let archive = format!("libLLVM.so.{major}.1");
+    let component = format!("libLLVM-{major}.so");
+    let dir = TempDir::new("invalid");
+    for name in ["libLLVMCore.a", "libLLVMBPFCodeGen.so", &archive] {
+        std::fs::write(dir.join(name), b"!<arch>\n").unwrap();
+    }
+    // A shared object that is not LLVM, standing in for a component library
+    // of a split build.
+    std::os::unix::fs::symlink(libc(), dir.join(&component)).unwrap();
+
+    let report = Loader::new().search_dir(&*dir).discover();
+
+    assert!(report
+        .candidates
+        .iter()
+        .all(|candidate| !candidate.path.starts_with(&*dir)));
+    let rejected: Vec<_> = report
+        .rejected
+        .iter()
+        .filter(|rejected| rejected.path.starts_with(&*dir))
+        .collect();
+    assert_eq!(rejected.len(), 2, "{rejected:?}");
+    let reason = |name| {
+        &rejected
+            .iter()
+            .find(|rejected| rejected.path == dir.join(name))
+            .unwrap()
+            .reason
+    };
+    assert!(matches!(reason(&archive), RejectReason::NotSharedObject));
+    assert!(matches!(
+        reason(&component),
+        RejectReason::MissingSymbol("LLVMContextCreate")
+    ));
+}
+
+#[test]
+fn discover_rejects_corrupt_sections() {
+    // A 64-bit little-endian shared object whose `.dynsym` and string table
+    // claim to be far larger than the file.
+    let mut elf = vec![0u8; 64 + 2 * 64];
+    elf[..6].copy_from_slice(b"\x7fELF\x02\x01");
+    elf[16..18].copy_from_slice(&3u16.to_le_bytes());
+    elf[40..48].copy_from_slice(&64u64.to_le_bytes());
+    elf[58..60].copy_from_slice(&64u16.to_le_bytes());
+    elf[60..62].copy_from_slice(&2u16.to_le_bytes());
+    for (index, kind) in [(0, 11u32), (1, 3)] {
+        let header = &mut elf[64 + index * 64..][..64];
+        header[4..8].copy_from_slice(&kind.to_le_bytes());
+        header[32..40].copy_from_slice(&(1u64 << 60).to_le_bytes());
+        header[40..44].copy_from_slice(&1u32.to_le_bytes());
+    }
+    let dir = TempDir::new("corrupt");
+    let name = format!("libLLVM.so.{}.1", aya_rustc_llvm_proxy::LLVM_SYS_MAJOR);
+    std::fs::write(dir.join(name), elf).unwrap();
+
+    let report = Loader::new().search_dir(&*dir).discover();
+
+    let rejected: Vec<_> = report
+        .rejected
+        .iter()
+        .filter(|rejected| rejected.path.starts_with(&*dir))
+        .collect();
+    assert_eq!(rejected.len(), 1);
+    assert!(
+        matches!(&rejected[0].reason, RejectReason::Read(err) if err.kind() == std::io::ErrorKind::InvalidData),
+        "{:?}",
+        rejected[0].reason
+    );
+}
+
+#[test]
+fn discover_version_policy() {
+    let Some(library) = std::env::var_os(CHILD_ENV_VAR) else {
+        let output = isolated_child("discover_version_policy", llvm_library().to_str().unwrap())
+            .output()
+            .unwrap();
+        assert!(output.status.success(), "{output:?}");
+        return;
+    };
+
+    let major = aya_rustc_llvm_proxy::LLVM_SYS_MAJOR;
+    let dir = TempDir::new("policy");
+    let mismatched = dir.join(format!("libLLVM.so.{}.1", major - 1));
+    std::os::unix::fs::symlink(&library, &mismatched).unwrap();
+    let choose = |policy| {
+        let report = Loader::new()
+            .search_dir(&*dir)
+            .version_policy(policy)
+            .discover();
+        // Nothing outside `dir` is found in the child.
+        assert!(report
+            .candidates
+            .iter()
+            .all(|candidate| candidate.path.starts_with(&*dir)));
+        let paths: Vec<_> = report
+            .candidates
+            .iter()
+            .map(|candidate| candidate.path.clone())
+            .collect();
+        report
+            .choice
+            .map(|Choice { index, reason }| (paths[index].clone(), reason))
+    };
+
+    // Only a mismatched name is found.
+    assert_eq!(choose(VersionPolicy::Strict), None);
+    assert_eq!(
+        choose(VersionPolicy::Warn),
+        Some((mismatched.clone(), ChoiceReason::MismatchedVersion))
+    );
+    assert_eq!(
+        choose(VersionPolicy::Ignore),
+        Some((mismatched.clone(), ChoiceReason::MismatchedVersion))
+    );
+
+    // A matching name is preferred under every policy.
+    let matching = dir.join(format!("libLLVM-{major}.so"));
+    std::os::unix::fs::symlink(&library, &matching).unwrap();
+    for policy in [
+        VersionPolicy::Strict,
+        VersionPolicy::Warn,
+        VersionPolicy::Ignore,
+    ] {
+        assert_eq!(
+            choose(policy),
+            Some((matching.clone(), ChoiceReason::MatchingVersion)),
+            "{policy:?}"
+        );
+    }
+}
+
+#[test]
+fn rank_candidates() {
+    let candidate = |path: &str, source, version: Option<(u32, u32)>| Candidate {
+        path: path.into(),
+        source,
+        version: version.map(|(major, minor)| Version {
+            major,
+            minor,
+            patch: 0,
+        }),
+        provider: if path.contains("rustc_driver") {
+            Provider::RustcDriver
+        } else {
+            Provider::LibLlvm
+        },
+    };
+    let major = aya_rustc_llvm_proxy::LLVM_SYS_MAJOR;
+    let mut candidates = [
+        candidate(
+            "/opt/rust/lib/librustc_driver-0123456789abcdef.so",
+            SearchSource::Sysroot,
+            None,
+        ),
+        candidate(
+            "/usr/lib/libLLVM.so.20.1",
+            SearchSource::Path,
+            Some((20, 1)),
+        ),
+        candidate("/usr/lib/libLLVM.so", SearchSource::Path, None),
+        candidate(
+            "/usr/lib/libLLVM.so.X.1",
+            SearchSource::LdLibraryPath,
+            Some((major, 1)),
+        ),
+        candidate(
+            "/opt/rust/lib/libLLVM.so.X.0-rust-1.90.0-stable",
+            SearchSource::LdLibraryPath,
+            Some((major, 0)),
+        ),
+        candidate(
+            "/usr/lib/libLLVM.so.X.2",
+            SearchSource::LdLibraryPath,
+            Some((major, 2)),
+        ),
+    ];
+    candidates.sort_by_key(|candidate| Reverse(candidate.rank()));
+    let paths: Vec<_> = candidates
+        .iter()
+        .map(|candidate| candidate.path.to_str().unwrap())
+        .collect();
+    assert_eq!(
+        paths,
+        [
+            "/opt/rust/lib/libLLVM.so.X.0-rust-1.90.0-stable",
+            "/usr/lib/libLLVM.so.X.2",
+            "/usr/lib/libLLVM.so.X.1",
+            "/usr/lib/libLLVM.so",
+            "/usr/lib/libLLVM.so.20.1",
+            "/opt/rust/lib/librustc_driver-0123456789abcdef.so",
+        ]
+    );
+}
+
+#[test]
+fn discover_dedups_symlinks() {
+    let chosen = aya_rustc_llvm_proxy::discover()
+        .chosen()
+        .expect("no candidate")
+        .path
+        .canonicalize()
+        .unwrap();
+    let dir = TempDir::new("link");
+    let link = dir.join(chosen.file_name().unwrap());
+    std::os::unix::fs::symlink(&chosen, &link).unwrap();
+    // An unversioned alias sorts first but ranks lower.
+    let alias = dir.join("libLLVM.so");
+    std::os::unix::fs::symlink(&chosen, &alias).unwrap();
+
+    let report = Loader::new().search_dir(&*dir).discover();
+    let canonical: Vec<_> = report
+        .candidates
+        .iter()
+        .map(|candidate| candidate.path.canonicalize().unwrap())
+        .collect();
+
+    assert_eq!(
+        canonical.iter().collect::<HashSet<_>>().len(),
+        canonical.len()
+    );
+    assert_eq!(report.chosen().unwrap().path, link);
+    assert!(report
+        .candidates
+        .iter()
+        .all(|candidate| candidate.path != alias));
+}
+
+#[test]
+fn discover_next_to_executable() {
+    let exe = std::env::current_exe().unwrap();
+    let origin = exe.parent().unwrap();
+    let report = aya_rustc_llvm_proxy::discover();
+    let dirs: Vec<_> = report
+        .searched
+        .iter()
+        .filter(|dir| dir.source == SearchSource::Executable)
+        .map(|dir| dir.path.as_path())
+        .collect();
+    assert_eq!(dirs, [origin, &origin.with_file_name("lib")]);
+}
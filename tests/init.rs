@@ -0,0 +1,128 @@
+extern crate aya_rustc_llvm_proxy;
+extern crate llvm_sys;
+
+/// Pairs each `llvm_sys::target` wrapper with the export in `init` that
+/// replaces it, failing to build if an export is missing.
+macro_rules! init_macros {
+    ($($name:ident),* $(,)?) => {
+        #[test]
+        fn init_exports_complete() {
+            let exports = [$(
+                (
+                    stringify!($name),
+                    llvm_sys::target::$name as *const (),
+                    aya_rustc_llvm_proxy::init::$name as *const (),
+                )
+            ),*];
+            for (name, wrapper, export) in exports {
+                assert_eq!(
+                    wrapper, export,
+                    "{name} does not resolve to the init export"
+                );
+            }
+        }
+    };
+}
+include!("../src/init_macros.rs");
+
+#[test]
+fn initialize_all() {
+    unsafe {
+        llvm_sys::target::LLVM_InitializeAllTargetInfos();
+        llvm_sys::target::LLVM_InitializeAllTargets();
+        llvm_sys::target::LLVM_InitializeAllTargetMCs();
+        llvm_sys::target::LLVM_InitializeAllAsmPrinters();
+        llvm_sys::target::LLVM_InitializeAllAsmParsers();
+        llvm_sys::target::LLVM_InitializeAllDisassemblers();
+    }
+}
+
+#[test]
+fn available_backends() {
+    let backends = aya_rustc_llvm_proxy::init::available_backends().unwrap();
+    assert!(backends.iter().any(|backend| backend == "BPF"));
+    assert!(backends.windows(2).all(|pair| pair[0] < pair[1]));
+}
+
+#[test]
+fn initialize_native() {
+    unsafe {
+        assert_eq!(llvm_sys::target::LLVM_InitializeNativeTarget(), 0);
+        assert_eq!(llvm_sys::target::LLVM_InitializeNativeAsmPrinter(), 0);
+        assert_eq!(llvm_sys::target::LLVM_InitializeNativeAsmParser(), 0);
+        assert_eq!(llvm_sys::target::LLVM_InitializeNativeDisassembler(), 0);
+    }
+}
+
+#[test]
+fn initialize_backend() {
+    use aya_rustc_llvm_proxy::init::{initialize, is_initialized, Backend, Component, InitError};
+
+    let threads: Vec<_> = (0..4)
+        .map(|_| {
+            std::thread::spawn(|| {
+                initialize(
+                    Backend::BPF,
+                    Component::TargetInfo
+                        | Component::Target
+                        | Component::TargetMC
+                        | Component::AsmPrinter,
+                )
+            })
+        })
+        .collect();
+    for thread in threads {
+        thread.join().unwrap().unwrap();
+    }
+    assert!(is_initialized(Backend::BPF, Component::AsmPrinter));
+
+    match initialize(Backend::NVPTX, Component::AsmParser) {
+        Err(InitError::MissingEntryPoint(name)) => {
+            assert_eq!(name, "LLVMInitializeNVPTXAsmParser")
+        }
+        result => panic!("unexpected result {result:?}"),
+    }
+}
+
+#[test]
+fn initialize_for_triple() {
+    use aya_rustc_llvm_proxy::init::{initialize_for_triple, Backend, Component, InitError};
+
+    let components = Component::Target | Component::TargetMC;
+    assert_eq!(
+        initialize_for_triple("bpfel-unknown-none", components).unwrap(),
+        Backend::BPF
+    );
+    assert_eq!(
+        initialize_for_triple("x86_64-unknown-linux-gnu", components).unwrap(),
+        Backend::X86
+    );
+    assert!(matches!(
+        initialize_for_triple("nonsense-unknown-none", components),
+        Err(InitError::UnknownArch(_))
+    ));
+}
+
+extern "C" {
+    // Not declared by llvm-sys.
+    fn LLVMInitializeAVRTargetInfo();
+}
+
+#[test]
+fn undeclared_target_initializers() {
+    use aya_rustc_llvm_proxy::proxy::{ProxySymbol, SYMBOLS};
+
+    assert!(SYMBOLS.contains(&ProxySymbol {
+        name: "LLVMInitializeAVRTargetInfo",
+        module: "llvm_sys::target",
+    }));
+    // Only components the backends define are proxied.
+    for name in [
+        "LLVMInitializeNVPTXAsmParser",
+        "LLVMInitializeSPIRVDisassembler",
+        "LLVMInitializeDirectXAsmParser",
+    ] {
+        assert!(!SYMBOLS.iter().any(|symbol| symbol.name == name), "{name}");
+    }
+    unsafe { LLVMInitializeAVRTargetInfo() };
+}
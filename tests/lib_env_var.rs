@@ -0,0 +1,88 @@
+extern crate aya_rustc_llvm_proxy;
+
+mod common;
+
+use aya_rustc_llvm_proxy::init::{initialize_for_triple, Component, InitError};
+use aya_rustc_llvm_proxy::{LoadError, LIB_ENV_VAR};
+use common::TempDir;
+use std::path::Path;
+use std::process::Command;
+
+/// Set in the child process, which loads with `LIB_ENV_VAR` set and prints
+/// the outcome.
+const CHILD_ENV_VAR: &str = "RUSTC_LLVM_PROXY_TEST_CHILD";
+
+fn load_in_child(lib: &Path) -> String {
+    let output = Command::new(std::env::current_exe().unwrap())
+        .args(["--exact", "lib_env_var", "--nocapture", "--test-threads=1"])
+        .env(CHILD_ENV_VAR, "1")
+        .env(LIB_ENV_VAR, lib)
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{output:?}");
+    String::from_utf8(output.stdout).unwrap()
+}
+
+#[test]
+fn lib_env_var() {
+    if std::env::var_os(CHILD_ENV_VAR).is_some() {
+        let error = match aya_rustc_llvm_proxy::try_load() {
+            Ok(_) => {
+                println!(
+                    "loaded {}",
+                    aya_rustc_llvm_proxy::library_path().unwrap().display()
+                );
+                return;
+            }
+            Err(error) => error,
+        };
+        match error.root() {
+            LoadError::Open { path, .. } => {
+                println!("unable to open {}", path.display());
+                // The failure is remembered rather than searched for again.
+                match aya_rustc_llvm_proxy::try_load() {
+                    Err(again) if std::ptr::eq(error.root(), again.root()) => {
+                        println!("cached")
+                    }
+                    again => println!("not cached: {again:?}"),
+                }
+                match initialize_for_triple("bpfel-unknown-none", Component::Target) {
+                    Err(InitError::Load(_)) => println!("initialize_for_triple: load error"),
+                    result => println!("initialize_for_triple: {result:?}"),
+                }
+            }
+            error => println!("failed: {error}"),
+        }
+        return;
+    }
+
+    // A symlink outside every searched directory is only found through the
+    // variable.
+    let library = aya_rustc_llvm_proxy::discover()
+        .chosen()
+        .expect("no candidate")
+        .path
+        .canonicalize()
+        .unwrap();
+    let dir = TempDir::new("env");
+    let link = dir.join("libLLVM-pinned.so");
+    std::os::unix::fs::symlink(&library, &link).unwrap();
+    let pinned = load_in_child(&link);
+    assert!(
+        pinned.contains(&format!("loaded {}", link.display())),
+        "{pinned}"
+    );
+
+    // A bad path fails instead of falling back to the search.
+    let missing = Path::new("/nonexistent/libLLVM.so");
+    let missing_output = load_in_child(missing);
+    assert!(
+        missing_output.contains(&format!("unable to open {}", missing.display())),
+        "{missing_output}"
+    );
+    assert!(missing_output.contains("cached\n"), "{missing_output}");
+    assert!(
+        missing_output.contains("initialize_for_triple: load error"),
+        "{missing_output}"
+    );
+}
@@ -1,3 +1,5 @@
+#![allow(deprecated)]
+
 extern crate aya_rustc_llvm_proxy;
 extern crate llvm_sys;
 
@@ -28,3 +30,29 @@ target datalayout = "e-i64:64-v16:16-v32:32-n16:32:64"
         LLVMDisposeModule(module);
     }
 }
+
+#[test]
+fn resolve_all_symbols() {
+    let report = aya_rustc_llvm_proxy::proxy::resolve_all().expect("unable to load LLVM");
+    assert!(report.resolved.contains(&"LLVMModuleCreateWithName"));
+    assert!(report.is_complete(), "missing {:?}", report.missing);
+    assert!(report
+        .missing_initializers
+        .iter()
+        .all(|name| name.starts_with("LLVMInitialize")));
+    // libLLVM does not export the libLTO API, but it is still proxied.
+    assert!(report.missing_unavailable.contains(&"lto_get_version"));
+    assert!(!report.missing_unavailable.contains(&"LLVMContextCreate"));
+}
+
+#[test]
+fn symbol_availability() {
+    use aya_rustc_llvm_proxy::proxy::{available, ProxySymbol, SYMBOLS};
+
+    assert!(SYMBOLS.contains(&ProxySymbol {
+        name: "LLVMRunPasses",
+        module: "llvm_sys::transforms::pass_builder",
+    }));
+    assert!(available("LLVMRunPasses"));
+    assert!(!available("LLVMDoesNotExist"));
+}
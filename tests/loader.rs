@@ -0,0 +1,125 @@
+extern crate aya_rustc_llvm_proxy;
+extern crate llvm_sys;
+
+mod common;
+
+use aya_rustc_llvm_proxy::{LoadError, Loader, RejectReason, Version, VersionPolicy};
+use common::{isolated_child, libc, llvm_library, TempDir, CHILD_ENV_VAR};
+use llvm_sys::core::{LLVMContextCreate, LLVMContextDispose};
+
+// Loading is process-wide, so the whole sequence lives in a single test.
+#[test]
+fn load_once() {
+    assert!(matches!(
+        Loader::new().path("/nonexistent/libLLVM.so").load(),
+        Err(LoadError::Open { .. })
+    ));
+
+    let library = Loader::new()
+        .version_policy(VersionPolicy::Strict)
+        .load()
+        .expect("unable to load LLVM");
+
+    unsafe {
+        let context = LLVMContextCreate();
+        LLVMContextDispose(context);
+    }
+
+    assert!(matches!(
+        Loader::new().load(),
+        Err(LoadError::AlreadyLoaded)
+    ));
+    assert!(std::ptr::eq(
+        library,
+        aya_rustc_llvm_proxy::try_load().unwrap()
+    ));
+
+    let version = aya_rustc_llvm_proxy::llvm_version().unwrap();
+    assert_eq!(version.major, aya_rustc_llvm_proxy::LLVM_SYS_MAJOR);
+    assert!(aya_rustc_llvm_proxy::LLVM_SYS_VERSION.starts_with(&format!("{}", version.major)));
+    assert!(aya_rustc_llvm_proxy::library_path().unwrap().is_file());
+    assert_eq!(
+        aya_rustc_llvm_proxy::provider(),
+        Some(aya_rustc_llvm_proxy::Provider::LibLlvm)
+    );
+}
+
+#[test]
+fn load_version_policy() {
+    let Some(mode) = std::env::var(CHILD_ENV_VAR).ok() else {
+        let library = llvm_library();
+        let dir = TempDir::new("load-policy");
+        let major = aya_rustc_llvm_proxy::LLVM_SYS_MAJOR;
+        let mismatched = dir.join(format!("libLLVM.so.{}.1", major - 1));
+        std::os::unix::fs::symlink(&library, &mismatched).unwrap();
+        for mode in [
+            "strict",
+            "warn",
+            "pinned-strict",
+            "pinned-warn",
+            "pinned-ignore",
+        ] {
+            let output = isolated_child("load_version_policy", mode)
+                .env("RUSTC_LLVM_PROXY_TEST_DIR", &*dir)
+                .output()
+                .unwrap();
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            assert!(output.status.success(), "{mode}: {stderr}");
+            // The warning goes to the logger when there is one.
+            let warned = stderr.contains("warning: LLVM shared lib");
+            let expected =
+                mode == "pinned-warn" && cfg!(not(any(feature = "log", feature = "tracing")));
+            assert_eq!(warned, expected, "{mode}: {stderr}");
+        }
+        return;
+    };
+
+    let dir = std::env::var_os("RUSTC_LLVM_PROXY_TEST_DIR").unwrap();
+    let major = aya_rustc_llvm_proxy::LLVM_SYS_MAJOR;
+    let mismatched = std::path::Path::new(&dir).join(format!("libLLVM.so.{}.1", major - 1));
+    let search = |policy| Loader::new().search_dir(&dir).version_policy(policy);
+    // libc exports no `LLVMGetVersion`, so its version is unknown.
+    let pinned = |policy| Loader::new().path(libc()).version_policy(policy);
+    match mode.as_str() {
+        // The file name is known to mismatch, so the library is not opened.
+        "strict" => match search(VersionPolicy::Strict).load() {
+            Err(LoadError::NotFound { rejected, .. }) => {
+                let rejected = rejected
+                    .iter()
+                    .find(|rejected| rejected.path == mismatched)
+                    .unwrap();
+                assert!(matches!(
+                    rejected.reason,
+                    RejectReason::VersionMismatch {
+                        expected,
+                        found: Some(Version { major: found, .. }),
+                    } if expected == major && found == major - 1
+                ));
+            }
+            result => panic!("{result:?}"),
+        },
+        // Falls back to the mismatched name, which matches once loaded.
+        "warn" => {
+            search(VersionPolicy::Warn).load().unwrap();
+            assert_eq!(aya_rustc_llvm_proxy::library_path(), Some(&*mismatched));
+        }
+        "pinned-strict" => match pinned(VersionPolicy::Strict).load() {
+            Err(LoadError::VersionMismatch {
+                path,
+                expected,
+                found: None,
+            }) => {
+                assert_eq!(path, libc());
+                assert_eq!(expected, major);
+            }
+            result => panic!("{result:?}"),
+        },
+        "pinned-warn" => {
+            pinned(VersionPolicy::Warn).load().unwrap();
+        }
+        "pinned-ignore" => {
+            pinned(VersionPolicy::Ignore).load().unwrap();
+        }
+        mode => panic!("unknown mode {mode}"),
+    }
+}
@@ -0,0 +1,59 @@
+extern crate aya_rustc_llvm_proxy;
+extern crate llvm_sys;
+
+use std::process::{Command, Output};
+
+/// Set in the child process to `handler` or `default`, selecting whether a
+/// missing symbol handler is installed before the missing symbol is called.
+const CHILD_ENV_VAR: &str = "RUSTC_LLVM_PROXY_TEST_CHILD";
+
+// Rust's LLVM is built without the XCore backend.
+const MISSING: &str = "LLVMInitializeXCoreTargetInfo";
+
+fn handler(name: &'static str) -> ! {
+    eprintln!("handled {name}");
+    std::process::exit(3)
+}
+
+fn call_missing_in_child(mode: &str) -> Output {
+    Command::new(std::env::current_exe().unwrap())
+        .args([
+            "--exact",
+            "missing_symbol",
+            "--nocapture",
+            "--test-threads=1",
+        ])
+        .env(CHILD_ENV_VAR, mode)
+        .output()
+        .unwrap()
+}
+
+#[test]
+fn missing_symbol() {
+    if let Some(mode) = std::env::var_os(CHILD_ENV_VAR) {
+        aya_rustc_llvm_proxy::try_load().expect("unable to load LLVM");
+        if mode == "handler" {
+            aya_rustc_llvm_proxy::set_missing_symbol_handler(handler);
+        }
+        unsafe { llvm_sys::target::LLVMInitializeXCoreTargetInfo() };
+        unreachable!("{MISSING} is exported");
+    }
+
+    let Output { status, stderr, .. } = call_missing_in_child("handler");
+    let stderr = String::from_utf8_lossy(&stderr);
+    assert_eq!(status.code(), Some(3), "{stderr}");
+    assert!(stderr.contains(&format!("handled {MISSING}")), "{stderr}");
+
+    let Output { status, stderr, .. } = call_missing_in_child("default");
+    let stderr = String::from_utf8_lossy(&stderr);
+    assert!(
+        stderr.contains(&format!("does not export {MISSING}")),
+        "{stderr}"
+    );
+    // Every mode ends in an abort: a panic cannot unwind out of the
+    // `extern "C"` proxy.
+    assert_eq!(status.code(), None, "{stderr}");
+    if cfg!(rustc_llvm_proxy_missing_symbol = "hook") {
+        assert!(stderr.contains("no missing symbol handler"), "{stderr}");
+    }
+}
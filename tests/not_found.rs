@@ -0,0 +1,77 @@
+extern crate aya_rustc_llvm_proxy;
+
+mod common;
+
+use aya_rustc_llvm_proxy::{LoadError, RejectReason, SearchSource, SearchedDir};
+use common::{isolated_child, TempDir, CHILD_ENV_VAR};
+use std::path::Path;
+
+#[test]
+fn not_found() {
+    let Some(dir) = std::env::var_os(CHILD_ENV_VAR) else {
+        let dir = TempDir::new("not-found");
+        let name = format!("libLLVM.so.{}.1", aya_rustc_llvm_proxy::LLVM_SYS_MAJOR);
+        std::fs::write(dir.join(name), b"!<arch>\n").unwrap();
+        let library_path = std::env::join_paths([&*dir, Path::new("/nonexistent")]).unwrap();
+        let output = isolated_child("not_found", dir.to_str().unwrap())
+            .env("LD_LIBRARY_PATH", library_path)
+            .output()
+            .unwrap();
+        assert!(output.status.success(), "{output:?}");
+        return;
+    };
+
+    let dir = Path::new(&dir);
+    let archive = dir.join(format!(
+        "libLLVM.so.{}.1",
+        aya_rustc_llvm_proxy::LLVM_SYS_MAJOR
+    ));
+    let error = aya_rustc_llvm_proxy::try_load().unwrap_err();
+    let LoadError::NotFound { searched, rejected } = error.root() else {
+        panic!("{error:?}");
+    };
+
+    let library_dirs: Vec<_> = searched
+        .iter()
+        .filter(|searched| searched.source == SearchSource::LdLibraryPath)
+        .collect();
+    assert!(matches!(
+        library_dirs[..],
+        [
+            SearchedDir {
+                path: found,
+                error: None,
+                ..
+            },
+            SearchedDir {
+                path: missing,
+                error: Some(_),
+                ..
+            },
+        ] if found == dir && missing == Path::new("/nonexistent")
+    ));
+    // `RUSTC` does not exist, so there is no sysroot to search.
+    assert!(searched
+        .iter()
+        .all(|searched| searched.source != SearchSource::Sysroot));
+    assert!(matches!(
+        &rejected[..],
+        [rejected] if rejected.path == archive
+            && matches!(rejected.reason, RejectReason::NotSharedObject)
+    ));
+
+    let message = error.to_string();
+    assert!(
+        message.starts_with("unable to find LLVM shared lib\n"),
+        "{message}"
+    );
+    assert!(
+        message.contains(&format!("\n  searched {}\n", dir.display())),
+        "{message}"
+    );
+    assert!(message.contains("\n  searched /nonexistent: "), "{message}");
+    assert!(
+        message.contains(&format!("\n  rejected {}: ", archive.display())),
+        "{message}"
+    );
+}